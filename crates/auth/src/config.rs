@@ -0,0 +1,95 @@
+use crate::Role;
+use serde::{Deserialize, Serialize};
+use settings::{SetupError, env};
+use std::path::PathBuf;
+
+/// Authentication of API callers via JWTs or API keys
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub enabled: bool,              // Reject requests without valid credentials
+    pub jwks_path: Option<PathBuf>, // JWKS file with the keys JWTs are signed with
+    pub issuer: Option<String>,     // Required `iss` claim of JWTs
+    pub audience: Option<String>,   // Required `aud` claim of JWTs
+    pub api_keys: Vec<ApiKey>,      // Keys of other services and scripts
+}
+
+/// API key for service-to-service calls, sent in the X-API-Key header
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKey {
+    pub subject: String,  // Name of the caller
+    pub roles: Vec<Role>, // Roles granted to the caller
+    pub key: String,      // The secret itself
+}
+
+impl AuthConfig {
+    /// Overrides the settings with the `AUTH_*` environment variables that are set
+    pub fn apply_env(&mut self) -> Result<(), SetupError> {
+        env("AUTH_ENABLED", &mut self.enabled)?;
+        if let Some(path) = std::env::var_os("AUTH_JWKS_PATH") {
+            self.jwks_path = Some(PathBuf::from(path));
+        }
+        if let Ok(issuer) = std::env::var("AUTH_ISSUER") {
+            self.issuer = Some(issuer);
+        }
+        if let Ok(audience) = std::env::var("AUTH_AUDIENCE") {
+            self.audience = Some(audience);
+        }
+        // Comma-separated <subject>:<role>[+<role>...]:<key> entries
+        if let Ok(value) = std::env::var("AUTH_API_KEYS") {
+            let invalid = |reason: String| SetupError::Env {
+                name: "AUTH_API_KEYS".into(),
+                reason,
+            };
+            self.api_keys = value
+                .split(',')
+                .filter(|entry| !entry.trim().is_empty())
+                .map(|entry| {
+                    let mut parts = entry.trim().splitn(3, ':');
+                    let (Some(subject), Some(roles), Some(key)) =
+                        (parts.next(), parts.next(), parts.next())
+                    else {
+                        return Err(invalid(
+                            "entries must look like <subject>:<role>[+<role>]:<key>".into(),
+                        ));
+                    };
+                    let roles = roles
+                        .split('+')
+                        .map(|role| role.parse().map_err(invalid))
+                        .collect::<Result<_, _>>()?;
+                    Ok(ApiKey {
+                        subject: subject.into(),
+                        roles,
+                        key: key.into(),
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(())
+    }
+
+    /// Checks that enabled authentication has credentials to check against
+    pub fn validate(&self) -> Result<(), SetupError> {
+        if self.enabled && self.jwks_path.is_none() && self.api_keys.is_empty() {
+            return Err(SetupError::Invalid(
+                "auth.enabled requires auth.jwks_path or auth.api_keys".into(),
+            ));
+        }
+        for api_key in &self.api_keys {
+            if api_key.subject.is_empty() || api_key.key.is_empty() || api_key.roles.is_empty() {
+                return Err(SetupError::Invalid(
+                    "auth.api_keys entries need a subject, a key and at least one role".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the API keys with `placeholder`, so the settings are safe to print
+    pub fn redact(&mut self, placeholder: &str) {
+        for api_key in &mut self.api_keys {
+            api_key.key = placeholder.into();
+        }
+    }
+}
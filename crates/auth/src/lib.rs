@@ -0,0 +1,261 @@
+//! Authentication and role-based authorization shared by the coffee shop services
+
+mod config;
+
+pub use config::{ApiKey, AuthConfig};
+
+use axum::{
+    Json,
+    extract::FromRequestParts,
+    http::{HeaderMap, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use jsonwebtoken::{DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use serde::{Deserialize, Serialize};
+use settings::SetupError;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Header carrying the API key of service-to-service calls
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Subject of every caller while authentication is disabled
+pub const ANONYMOUS: &str = "anonymous";
+
+/// What a caller may do; admins may do everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Customer, // Places and follows their own orders
+    Barista,  // Serves at the counter, refills the inventory and maintains the machines
+    Service,  // Another service of the coffee shop, e.g. the machine deducting stock
+    Admin,    // Manages everything
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "customer" => Ok(Role::Customer),
+            "barista" => Ok(Role::Barista),
+            "service" => Ok(Role::Service),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role {}", other)),
+        }
+    }
+}
+
+/// Authenticated caller of a request
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub subject: String,  // Customer id, user name or service name
+    pub roles: Vec<Role>, // Roles granted to the caller
+}
+
+impl Principal {
+    /// Does the caller have one of the roles? Admins have all of them
+    pub fn has_any(&self, roles: &[Role]) -> bool {
+        self.roles
+            .iter()
+            .any(|role| *role == Role::Admin || roles.contains(role))
+    }
+
+    /// Fails with 403 Forbidden unless the caller has one of the roles
+    pub fn require(&self, roles: &[Role]) -> Result<(), AuthError> {
+        if self.has_any(roles) {
+            Ok(())
+        } else {
+            tracing::warn!(subject = %self.subject, "Request denied, the caller lacks the role");
+            Err(AuthError::Forbidden)
+        }
+    }
+}
+
+/// Why a request was not authorized
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("Authentication required")]
+    Missing, // Neither a bearer token nor an API key was sent
+    #[error("Invalid credentials: {0}")]
+    Invalid(String), // The credentials were rejected
+    #[error("Not allowed for this role")]
+    Forbidden, // The caller lacks the required role
+}
+
+impl AuthError {
+    /// 401 for missing or rejected credentials, 403 for a missing role
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::Missing | AuthError::Invalid(_) => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+/// Lets handlers `?` an auth error into the error response body of their service
+impl<T: From<AuthError>> From<AuthError> for (StatusCode, Json<T>) {
+    fn from(err: AuthError) -> Self {
+        (err.status(), Json(T::from(err)))
+    }
+}
+
+/// Body of a rejected request, the same shape as the services' own error responses
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl From<AuthError> for ErrorBody {
+    fn from(err: AuthError) -> Self {
+        ErrorBody {
+            error: err.to_string(),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let challenge = self.status() == StatusCode::UNAUTHORIZED;
+        let mut response = <(StatusCode, Json<ErrorBody>)>::from(self).into_response();
+        if challenge {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                header::HeaderValue::from_static("Bearer"),
+            );
+        }
+        response
+    }
+}
+
+/// Claims read from a JWT
+#[derive(Deserialize)]
+struct Claims {
+    sub: String, // Becomes the principal's subject
+    #[serde(default)]
+    roles: Vec<String>, // Roles this service does not know are ignored
+}
+
+/// Verifies bearer tokens against a local JWKS file and API keys against the configuration
+pub struct Authenticator {
+    enabled: bool,                            // Without auth every caller is an admin
+    keys: Vec<(Option<String>, DecodingKey)>, // JWT verification keys by key id
+    issuer: Option<String>,                   // Required `iss` claim
+    audience: Option<String>,                 // Required `aud` claim
+    api_keys: HashMap<String, Principal>,     // Callers by API key
+}
+
+impl Authenticator {
+    /// Loads the JWKS file and API keys of the configuration
+    pub fn new(config: &AuthConfig) -> Result<Self, SetupError> {
+        let mut keys = Vec::new();
+        if let Some(path) = &config.jwks_path {
+            let invalid = |reason: String| SetupError::Parse {
+                path: path.display().to_string(),
+                reason: format!("invalid JWKS: {}", reason),
+            };
+            let text = std::fs::read_to_string(path).map_err(|source| SetupError::Read {
+                path: path.display().to_string(),
+                source,
+            })?;
+            let jwks: JwkSet = serde_json::from_str(&text).map_err(|e| invalid(e.to_string()))?;
+            for jwk in &jwks.keys {
+                let key = DecodingKey::from_jwk(jwk).map_err(|e| invalid(e.to_string()))?;
+                keys.push((jwk.common.key_id.clone(), key));
+            }
+        }
+
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|api_key| {
+                let principal = Principal {
+                    subject: api_key.subject.clone(),
+                    roles: api_key.roles.clone(),
+                };
+                (api_key.key.clone(), principal)
+            })
+            .collect();
+
+        Ok(Self {
+            enabled: config.enabled,
+            keys,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+            api_keys,
+        })
+    }
+
+    /// Identifies the caller by its API key or bearer token
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AuthError> {
+        if !self.enabled {
+            return Ok(Principal {
+                subject: ANONYMOUS.into(),
+                roles: vec![Role::Admin],
+            });
+        }
+
+        if let Some(key) = headers.get(API_KEY_HEADER) {
+            return key
+                .to_str()
+                .ok()
+                .and_then(|key| self.api_keys.get(key))
+                .cloned()
+                .ok_or_else(|| AuthError::Invalid("Unknown API key".into()));
+        }
+
+        let token = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(AuthError::Missing)?;
+        self.verify(token)
+    }
+
+    /// Checks the signature, expiry, issuer and audience of a JWT
+    fn verify(&self, token: &str) -> Result<Principal, AuthError> {
+        let invalid = |e: jsonwebtoken::errors::Error| AuthError::Invalid(e.to_string());
+
+        // The key id picks the key; a JWKS with a single key also accepts tokens without one
+        let header = decode_header(token).map_err(invalid)?;
+        let key = match &header.kid {
+            Some(kid) => self.keys.iter().find(|(id, _)| id.as_ref() == Some(kid)),
+            None if self.keys.len() == 1 => self.keys.first(),
+            None => None,
+        }
+        .map(|(_, key)| key)
+        .ok_or_else(|| AuthError::Invalid("Unknown signing key".into()))?;
+
+        // Keys only verify algorithms of their own family, e.g. an RSA key never accepts HS256
+        let mut validation = Validation::new(header.alg);
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        let claims = decode::<Claims>(token, key, &validation)
+            .map_err(invalid)?
+            .claims;
+        Ok(Principal {
+            subject: claims.sub,
+            roles: claims.roles.iter().filter_map(|r| r.parse().ok()).collect(),
+        })
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for Principal {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let authenticator = parts
+            .extensions
+            .get::<Arc<Authenticator>>()
+            .expect("Authenticator extension missing");
+        authenticator.authenticate(&parts.headers)
+    }
+}
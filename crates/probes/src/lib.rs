@@ -0,0 +1,73 @@
+//! Liveness and readiness responses shared by the coffee shop services; each service checks its
+//! own dependencies for readiness
+
+use axum::{Json, http::StatusCode};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+/// Whether a service or one of its dependencies is usable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+/// Result of checking a single dependency
+#[derive(Serialize, ToSchema)]
+pub struct Check {
+    pub status: HealthStatus,
+    pub details: String, // Human-readable explanation of the status
+}
+
+impl Check {
+    /// A dependency that is usable
+    pub fn up(details: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Up,
+            details: details.into(),
+        }
+    }
+
+    /// A dependency that is unavailable
+    pub fn down(details: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Down,
+            details: details.into(),
+        }
+    }
+}
+
+/// Response payload of the health endpoints
+#[derive(Serialize, ToSchema)]
+pub struct HealthResponse {
+    pub status: HealthStatus,            // Down if any check is down
+    pub checks: BTreeMap<String, Check>, // Checked dependencies by name
+}
+
+impl HealthResponse {
+    /// Combines the checks into a response with a matching HTTP status
+    pub fn from_checks(checks: BTreeMap<String, Check>) -> (StatusCode, Json<Self>) {
+        let up = checks.values().all(|c| c.status == HealthStatus::Up);
+        let (code, status) = if up {
+            (StatusCode::OK, HealthStatus::Up)
+        } else {
+            (StatusCode::SERVICE_UNAVAILABLE, HealthStatus::Down)
+        };
+        (code, Json(Self { status, checks }))
+    }
+}
+
+/// Handler for GET /health/live, up as long as the process serves requests
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "Health",
+    responses(
+        (status = 200, description = "The service is running", body = HealthResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_live() -> (StatusCode, Json<HealthResponse>) {
+    HealthResponse::from_checks(BTreeMap::new())
+}
@@ -0,0 +1,53 @@
+//! Config file loading, environment overrides and setup errors shared by the coffee shop services
+
+use serde::de::DeserializeOwned;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Placeholder printed instead of secrets
+pub const REDACTED: &str = "***";
+
+/// Errors in the settings of a service or the files they point to
+#[derive(Debug, Error)]
+pub enum SetupError {
+    #[error("Failed to read {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse {path}: {reason}")]
+    Parse { path: String, reason: String },
+    #[error("Invalid {name}: {reason}")]
+    Env { name: String, reason: String },
+    #[error("Invalid configuration: {0}")]
+    Invalid(String),
+}
+
+/// Reads a TOML config file; missing keys keep their defaults if `T` is `#[serde(default)]`
+pub fn from_file<T: DeserializeOwned>(path: &Path) -> Result<T, SetupError> {
+    let text = std::fs::read_to_string(path).map_err(|source| SetupError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+    toml::from_str(&text).map_err(|e| SetupError::Parse {
+        path: path.display().to_string(),
+        reason: e.to_string(),
+    })
+}
+
+/// Overrides `target` with the environment variable `name`, if it is set
+pub fn env<T>(name: &str, target: &mut T) -> Result<(), SetupError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = std::env::var(name) {
+        *target = value.parse().map_err(|e: T::Err| SetupError::Env {
+            name: name.into(),
+            reason: e.to_string(),
+        })?;
+    }
+    Ok(())
+}
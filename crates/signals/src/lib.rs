@@ -0,0 +1,34 @@
+//! Shutdown on SIGINT or SIGTERM, shared by the coffee shop services
+
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Resolves once SIGINT or SIGTERM is received
+pub async fn shutdown() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    info!("Shutdown signal received");
+}
+
+/// Cancels the token once SIGINT or SIGTERM is received
+pub async fn cancel_on_shutdown(token: CancellationToken) {
+    shutdown().await;
+    token.cancel();
+}
@@ -0,0 +1,52 @@
+use lapin::types::{AMQPValue, FieldTable};
+use opentelemetry::{
+    Context, global,
+    propagation::{Extractor, Injector},
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Writes trace context into AMQP message headers
+struct AmqpHeaderInjector<'a>(&'a mut FieldTable);
+
+impl Injector for AmqpHeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0
+            .insert(key.into(), AMQPValue::LongString(value.into()));
+    }
+}
+
+/// Reads trace context from AMQP message headers
+struct AmqpHeaderExtractor<'a>(&'a FieldTable);
+
+impl Extractor for AmqpHeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        match self.0.inner().get(key) {
+            Some(AMQPValue::LongString(value)) => std::str::from_utf8(value.as_bytes()).ok(),
+            _ => None,
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.inner().keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// AMQP headers carrying the trace context of the current span
+pub fn amqp_headers() -> FieldTable {
+    let mut headers = FieldTable::default();
+    let cx = tracing::Span::current().context();
+    global::get_text_map_propagator(|p| {
+        p.inject_context(&cx, &mut AmqpHeaderInjector(&mut headers))
+    });
+    headers
+}
+
+/// Trace context of the sender of an AMQP message, empty if it was sent without one
+pub fn amqp_context(headers: Option<&FieldTable>) -> Context {
+    match headers {
+        Some(headers) => {
+            global::get_text_map_propagator(|p| p.extract(&AmqpHeaderExtractor(headers)))
+        }
+        None => Context::new(),
+    }
+}
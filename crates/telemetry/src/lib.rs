@@ -0,0 +1,79 @@
+//! Logging, OpenTelemetry span export and trace context propagation shared by the coffee shop
+//! services
+
+#[cfg(feature = "amqp")]
+mod amqp;
+
+#[cfg(feature = "amqp")]
+pub use amqp::{amqp_context, amqp_headers};
+
+use axum::{extract::Request, http::HeaderMap, middleware::Next, response::Response};
+use opentelemetry::{global, trace::TracerProvider as _};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_sdk::{Resource, propagation::TraceContextPropagator, trace::SdkTracerProvider};
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    EnvFilter, Layer, filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt,
+};
+
+/// Sets up logging and, if `OTEL_EXPORTER_OTLP_ENDPOINT` is set, exports spans via OTLP.
+/// The returned provider has to be shut down on exit to flush pending spans.
+pub fn init(
+    service_name: &'static str,
+) -> Result<Option<SdkTracerProvider>, Box<dyn std::error::Error>> {
+    // Trace context travels in W3C `traceparent` headers
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let provider = match std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(_) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .build()?;
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(Resource::builder().with_service_name(service_name).build())
+                    .build(),
+            )
+        }
+        Err(_) => None,
+    };
+
+    // Logs follow RUST_LOG, spans are exported from info level regardless
+    let otel = provider.as_ref().map(|p| {
+        tracing_opentelemetry::layer()
+            .with_tracer(p.tracer(service_name))
+            .with_filter(LevelFilter::INFO)
+    });
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(EnvFilter::from_default_env()))
+        .with(otel)
+        .init();
+
+    Ok(provider)
+}
+
+/// Middleware running every HTTP request in a span that continues the caller's trace, if any
+pub async fn trace_request(req: Request, next: Next) -> Response {
+    let parent = global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(req.headers())));
+    let span = tracing::info_span!(
+        "http_request",
+        otel.name = %format!("{} {}", req.method(), req.uri().path()),
+        otel.kind = "server",
+        http.status_code = tracing::field::Empty,
+    );
+    span.set_parent(parent);
+
+    let response = next.run(req).instrument(span.clone()).await;
+    span.record("http.status_code", response.status().as_u16());
+    response
+}
+
+/// HTTP headers carrying the trace context of the current span, for outgoing requests
+pub fn http_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let cx = tracing::Span::current().context();
+    global::get_text_map_propagator(|p| p.inject_context(&cx, &mut HeaderInjector(&mut headers)));
+    headers
+}
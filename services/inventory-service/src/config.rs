@@ -0,0 +1,272 @@
+use crate::purchase_orders::ReorderPolicy;
+use auth::AuthConfig;
+use chrono::TimeDelta;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use settings::{REDACTED, SetupError, env};
+use std::path::PathBuf;
+
+/// Command line options, they take precedence over the config file and the environment
+#[derive(Debug, Parser)]
+#[command(version, about = "Keeps the coffee machines' ingredient stock")]
+pub struct Cli {
+    /// TOML file to read the configuration from [env: CONFIG_FILE]
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration with secrets redacted, then exit
+    #[arg(long)]
+    pub print_config: bool,
+    /// Port of the HTTP server
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Comma-separated list of machines sharing the shop
+    #[arg(long, value_delimiter = ',')]
+    pub machine_ids: Option<Vec<String>>,
+}
+
+/// Configuration of the inventory service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub port: u16,                          // Port of the HTTP server
+    pub machine_ids: Vec<String>,           // Machines sharing the shop
+    pub default_machine_id: Option<String>, // Machine of the plain /fill endpoints
+    pub milk: MilkConfig,                   // Milk lot expiry
+    pub forecast: ForecastConfig,           // Consumption forecast
+    pub reorder: ReorderConfig,             // Purchase order drafting
+    pub auth: AuthConfig,                   // Authentication of API callers
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: 8081,
+            machine_ids: vec!["machine-1".into()],
+            default_machine_id: None,
+            milk: MilkConfig::default(),
+            forecast: ForecastConfig::default(),
+            reorder: ReorderConfig::default(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+/// Longest duration the settings accept, in hours: a year
+const MAX_HOURS: i64 = 24 * 365;
+
+/// Expiry of milk lots
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MilkConfig {
+    pub shelf_life_hours: i64,    // Default expiry of a refilled milk lot
+    pub sweep_interval_secs: u64, // How often expired lots are written off
+}
+
+impl Default for MilkConfig {
+    fn default() -> Self {
+        Self {
+            shelf_life_hours: 168,
+            sweep_interval_secs: 60,
+        }
+    }
+}
+
+/// Window and horizon of the consumption forecast
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ForecastConfig {
+    pub window_minutes: i64, // Past consumption the rate is computed from
+    pub horizon_hours: i64,  // How far ahead stock-outs are predicted
+}
+
+impl Default for ForecastConfig {
+    fn default() -> Self {
+        Self {
+            window_minutes: 60,
+            horizon_hours: 8,
+        }
+    }
+}
+
+/// Reorder policies per ingredient and the supplier's lead time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReorderConfig {
+    pub beans: ReorderPolicy, // Reorder settings for beans
+    pub milk: ReorderPolicy,  // Reorder settings for milk
+    pub lead_time_hours: i64, // Time between ordering and delivery
+}
+
+impl Default for ReorderConfig {
+    fn default() -> Self {
+        Self {
+            beans: ReorderPolicy {
+                reorder_point: 5,
+                reorder_quantity: 20,
+            },
+            milk: ReorderPolicy {
+                reorder_point: 3,
+                reorder_quantity: 10,
+            },
+            lead_time_hours: 24,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the defaults, overridden by the config file, the environment and the command line
+    /// in that order, and validates the result
+    pub fn load(cli: &Cli) -> Result<Self, SetupError> {
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| std::env::var_os("CONFIG_FILE").map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => settings::from_file(&path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+
+        if let Some(port) = cli.port {
+            config.port = port;
+        }
+        if let Some(machine_ids) = &cli.machine_ids {
+            config.machine_ids = machine_ids.clone();
+        }
+
+        // ids may come with blanks around the commas
+        config.machine_ids = config
+            .machine_ids
+            .iter()
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty())
+            .collect();
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Overrides settings with the environment variables that are set
+    fn apply_env(&mut self) -> Result<(), SetupError> {
+        env("SERVICE_PORT", &mut self.port)?;
+        if let Ok(ids) = std::env::var("MACHINE_IDS") {
+            self.machine_ids = ids.split(',').map(String::from).collect();
+        }
+        if let Ok(id) = std::env::var("DEFAULT_MACHINE_ID") {
+            self.default_machine_id = Some(id);
+        }
+
+        let milk = &mut self.milk;
+        env("MILK_SHELF_LIFE_HOURS", &mut milk.shelf_life_hours)?;
+        env("LOT_SWEEP_INTERVAL_SECS", &mut milk.sweep_interval_secs)?;
+
+        let forecast = &mut self.forecast;
+        env("FORECAST_WINDOW_MINUTES", &mut forecast.window_minutes)?;
+        env("FORECAST_HORIZON_HOURS", &mut forecast.horizon_hours)?;
+
+        let (beans, milk) = (&mut self.reorder.beans, &mut self.reorder.milk);
+        env("BEANS_REORDER_POINT", &mut beans.reorder_point)?;
+        env("BEANS_REORDER_QUANTITY", &mut beans.reorder_quantity)?;
+        env("MILK_REORDER_POINT", &mut milk.reorder_point)?;
+        env("MILK_REORDER_QUANTITY", &mut milk.reorder_quantity)?;
+        env(
+            "SUPPLIER_LEAD_TIME_HOURS",
+            &mut self.reorder.lead_time_hours,
+        )?;
+        self.auth.apply_env()?;
+        Ok(())
+    }
+
+    /// Checks the settings are usable, so a misconfigured service fails at startup
+    pub fn validate(&self) -> Result<(), SetupError> {
+        let invalid = |reason: &str| Err(SetupError::Invalid(reason.into()));
+
+        if self.machine_ids.is_empty() {
+            return invalid("machine_ids must name at least one machine");
+        }
+        let unknown_default = self
+            .default_machine_id
+            .as_ref()
+            .filter(|id| !self.machine_ids.contains(id));
+        if let Some(id) = unknown_default {
+            return Err(SetupError::Invalid(format!(
+                "default_machine_id {} is not in machine_ids",
+                id
+            )));
+        }
+        if !(1..=MAX_HOURS).contains(&self.milk.shelf_life_hours) {
+            return Err(SetupError::Invalid(format!(
+                "milk.shelf_life_hours must be between 1 and {}",
+                MAX_HOURS
+            )));
+        }
+        if self.milk.sweep_interval_secs == 0 {
+            return invalid("milk.sweep_interval_secs must be positive");
+        }
+        if !(1..=MAX_HOURS * 60).contains(&self.forecast.window_minutes) {
+            return Err(SetupError::Invalid(format!(
+                "forecast.window_minutes must be between 1 and {}",
+                MAX_HOURS * 60
+            )));
+        }
+        if !(1..=MAX_HOURS).contains(&self.forecast.horizon_hours) {
+            return Err(SetupError::Invalid(format!(
+                "forecast.horizon_hours must be between 1 and {}",
+                MAX_HOURS
+            )));
+        }
+        if !(0..=MAX_HOURS).contains(&self.reorder.lead_time_hours) {
+            return Err(SetupError::Invalid(format!(
+                "reorder.lead_time_hours must be between 0 and {}",
+                MAX_HOURS
+            )));
+        }
+        self.auth.validate()?;
+        Ok(())
+    }
+
+    /// Machine addressed by the plain /fill endpoints
+    pub fn default_machine(&self) -> String {
+        self.default_machine_id
+            .clone()
+            .unwrap_or_else(|| self.machine_ids[0].clone())
+    }
+
+    /// Copy of the configuration with API keys replaced, safe to print
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        config.auth.redact(REDACTED);
+        config
+    }
+}
+
+impl MilkConfig {
+    /// Default expiry of a refilled milk lot
+    pub fn shelf_life(&self) -> TimeDelta {
+        hours(self.shelf_life_hours)
+    }
+}
+
+impl ForecastConfig {
+    /// Past consumption the rate is computed from
+    pub fn window(&self) -> TimeDelta {
+        TimeDelta::try_minutes(self.window_minutes).unwrap_or(TimeDelta::MAX)
+    }
+
+    /// How far ahead stock-outs are predicted
+    pub fn horizon(&self) -> TimeDelta {
+        hours(self.horizon_hours)
+    }
+}
+
+impl ReorderConfig {
+    /// Time between ordering and delivery
+    pub fn lead_time(&self) -> TimeDelta {
+        hours(self.lead_time_hours)
+    }
+}
+
+/// Converts hours to a TimeDelta, the longest one if out of range; `Config::validate` bounds them
+fn hours(hours: i64) -> TimeDelta {
+    TimeDelta::try_hours(hours).unwrap_or(TimeDelta::MAX)
+}
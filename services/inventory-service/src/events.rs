@@ -0,0 +1,75 @@
+use crate::ingredient::Ingredient;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use utoipa::ToSchema;
+
+/// Maximum number of events kept in memory
+const MAX_EVENTS: usize = 100;
+
+/// Kinds of noteworthy things that happen to the inventory
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    /// An expired lot was removed from stock
+    LotWrittenOff {
+        machine_id: String,
+        ingredient: Ingredient,
+        lot_id: u64,
+        quantity: u32,
+        expired_at: DateTime<Utc>,
+    },
+    /// A draft purchase order was created because stock is running low
+    PurchaseOrderDrafted {
+        purchase_order_id: u64,
+        ingredient: Ingredient,
+        quantity: u32,
+    },
+    /// A purchase order was delivered and its quantity added to stock
+    PurchaseOrderDelivered {
+        purchase_order_id: u64,
+        ingredient: Ingredient,
+        machine_id: String,
+        received_quantity: u32,
+    },
+    /// Stock was moved from one machine to another
+    StockTransferred {
+        from: String,
+        to: String,
+        beans: u32,
+        milk: u32,
+    },
+}
+
+/// A single inventory event with its timestamp
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct InventoryEvent {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+/// Bounded in-memory log of the most recent inventory events
+#[derive(Debug, Default)]
+pub struct EventLog {
+    events: VecDeque<InventoryEvent>,
+}
+
+impl EventLog {
+    /// Records an event, dropping the oldest one if the log is full
+    pub fn record(&mut self, kind: EventKind) {
+        tracing::info!(event = ?kind, "Inventory event");
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(InventoryEvent {
+            at: Utc::now(),
+            kind,
+        });
+    }
+
+    /// Returns all events, newest first
+    pub fn recent(&self) -> Vec<InventoryEvent> {
+        self.events.iter().rev().cloned().collect()
+    }
+}
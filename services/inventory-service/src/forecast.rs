@@ -0,0 +1,130 @@
+use crate::ingredient::Ingredient;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use utoipa::ToSchema;
+
+/// A single recorded deduction from the inventory
+#[derive(Debug, Clone)]
+struct Deduction {
+    at: DateTime<Utc>,
+    beans: u32,
+    milk: u32,
+}
+
+/// Forecast for a single ingredient
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IngredientForecast {
+    pub ingredient: Ingredient,      // Ingredient this forecast is for
+    pub stock: u32,                  // Currently usable stock
+    pub consumption_per_hour: f64,   // Rolling average consumption rate
+    pub hours_to_empty: Option<f64>, // None if nothing is being consumed
+    pub estimated_empty_at: Option<DateTime<Utc>>, // Point in time the stock runs out
+    pub suggested_refill: u32,       // Amount needed to last the horizon
+}
+
+/// Response payload for GET /inventory/forecast
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ForecastResponse {
+    pub window_minutes: i64, // Length of the history used for the rates
+    pub horizon_hours: i64,  // Period the suggested refills should cover
+    pub ingredients: Vec<IngredientForecast>,
+}
+
+/// Keeps a rolling window of deductions and derives consumption forecasts from it
+#[derive(Debug)]
+pub struct Forecaster {
+    history: VecDeque<Deduction>,
+    window: chrono::Duration,  // how far back deductions are considered
+    horizon: chrono::Duration, // how long a suggested refill should last
+    started_at: DateTime<Utc>, // history is incomplete before this point
+}
+
+impl Forecaster {
+    /// Creates a forecaster with an empty history
+    pub fn new(window: chrono::Duration, horizon: chrono::Duration) -> Self {
+        Self {
+            history: VecDeque::new(),
+            window,
+            horizon,
+            started_at: Utc::now(),
+        }
+    }
+
+    /// Records a successful deduction and drops entries that left the window
+    pub fn record(&mut self, beans: u32, milk: u32, now: DateTime<Utc>) {
+        self.history.push_back(Deduction {
+            at: now,
+            beans,
+            milk,
+        });
+        self.prune(now);
+    }
+
+    /// Builds the forecast for the given usable stock levels
+    pub fn forecast(&mut self, beans: u32, milk: u32, now: DateTime<Utc>) -> ForecastResponse {
+        self.prune(now);
+
+        // Only the part of the window the service has actually observed counts
+        let observed_since = (now - self.window).max(self.started_at);
+        let observed_hours = ((now - observed_since).num_seconds() as f64 / 3600.0).max(1.0 / 60.0);
+
+        // Large deductions can add up past u32
+        let used_beans: u64 = self.history.iter().map(|d| u64::from(d.beans)).sum();
+        let used_milk: u64 = self.history.iter().map(|d| u64::from(d.milk)).sum();
+
+        ForecastResponse {
+            window_minutes: self.window.num_minutes(),
+            horizon_hours: self.horizon.num_hours(),
+            ingredients: vec![
+                self.ingredient(
+                    Ingredient::Beans,
+                    beans,
+                    used_beans as f64 / observed_hours,
+                    now,
+                ),
+                self.ingredient(
+                    Ingredient::Milk,
+                    milk,
+                    used_milk as f64 / observed_hours,
+                    now,
+                ),
+            ],
+        }
+    }
+
+    /// Computes time-to-empty and a refill suggestion for one ingredient
+    fn ingredient(
+        &self,
+        ingredient: Ingredient,
+        stock: u32,
+        rate: f64,
+        now: DateTime<Utc>,
+    ) -> IngredientForecast {
+        let hours_to_empty = (rate > 0.0).then(|| stock as f64 / rate);
+        // A trickle of consumption pushes the point past what a timestamp can hold, report none then
+        let estimated_empty_at = hours_to_empty
+            .and_then(|h| chrono::Duration::try_seconds((h * 3600.0).round() as i64))
+            .and_then(|d| now.checked_add_signed(d));
+
+        // Suggest enough to cover the expected consumption over the horizon
+        let needed = (rate * self.horizon.num_minutes() as f64 / 60.0).ceil() as u32;
+
+        IngredientForecast {
+            ingredient,
+            stock,
+            consumption_per_hour: rate,
+            hours_to_empty,
+            estimated_empty_at,
+            suggested_refill: needed.saturating_sub(stock),
+        }
+    }
+
+    /// Removes deductions older than the window
+    fn prune(&mut self, now: DateTime<Utc>) {
+        let cutoff = now - self.window;
+        while self.history.front().is_some_and(|d| d.at < cutoff) {
+            self.history.pop_front();
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use crate::SharedInventory;
+use axum::{Json, extract::Extension, http::StatusCode};
+use probes::{Check, HealthResponse};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How long the readiness check waits for the inventory lock before reporting storage as down
+const STORAGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Handler for GET /health/ready, up while the inventory storage can be accessed
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "Health",
+    responses(
+        (status = 200, description = "The service serves inventory requests", body = HealthResponse, content_type = "application/json"),
+        (status = 503, description = "The inventory storage is unavailable", body = HealthResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_ready(
+    Extension(state): Extension<SharedInventory>,
+) -> (StatusCode, Json<HealthResponse>) {
+    // A lock held for too long would stall every inventory request
+    let storage = match tokio::time::timeout(STORAGE_TIMEOUT, state.lock()).await {
+        Ok(inv) => Check::up(format!(
+            "In-memory inventory of {} machines",
+            inv.machines.len()
+        )),
+        Err(_) => Check::down(format!(
+            "Inventory lock not acquired within {:?}",
+            STORAGE_TIMEOUT
+        )),
+    };
+    HealthResponse::from_checks(BTreeMap::from([("storage".to_string(), storage)]))
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Ingredients managed by the inventory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Ingredient {
+    Beans,
+    Milk,
+}
+
+impl Ingredient {
+    /// Capitalized name for use at the start of messages
+    pub fn title(&self) -> &'static str {
+        match self {
+            Ingredient::Beans => "Beans",
+            Ingredient::Milk => "Milk",
+        }
+    }
+}
+
+impl fmt::Display for Ingredient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ingredient::Beans => write!(f, "beans"),
+            Ingredient::Milk => write!(f, "milk"),
+        }
+    }
+}
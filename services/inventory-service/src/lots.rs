@@ -0,0 +1,112 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// A single delivery of a perishable ingredient
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Lot {
+    pub id: u64,                     // Unique lot number
+    pub quantity: u32,               // Remaining quantity in this lot
+    pub delivered_at: DateTime<Utc>, // When the lot was received
+    pub expires_at: DateTime<Utc>,   // Best-before date of the lot
+}
+
+impl Lot {
+    /// Returns true if the lot can no longer be used at the given time
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// Stock of a perishable ingredient, split into lots ordered by expiry date
+#[derive(Debug, Default)]
+pub struct LotStore {
+    lots: Vec<Lot>, // sorted by expires_at, earliest first
+    next_id: u64,
+}
+
+impl LotStore {
+    /// Adds a new lot, keeping the lots ordered by expiry date
+    pub fn receive(
+        &mut self,
+        quantity: u32,
+        delivered_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> &Lot {
+        self.next_id += 1;
+        let lot = Lot {
+            id: self.next_id,
+            quantity,
+            delivered_at,
+            expires_at,
+        };
+        let pos = self.lots.partition_point(|l| l.expires_at <= expires_at);
+        self.lots.insert(pos, lot);
+        &self.lots[pos]
+    }
+
+    /// Total quantity across all lots, expired or not
+    pub fn total(&self) -> u32 {
+        self.lots.iter().map(|l| l.quantity).sum()
+    }
+
+    /// Quantity that has not expired yet at the given time
+    pub fn usable(&self, now: DateTime<Utc>) -> u32 {
+        self.lots
+            .iter()
+            .filter(|l| !l.is_expired(now))
+            .map(|l| l.quantity)
+            .sum()
+    }
+
+    /// Quantity that has expired but not been written off yet
+    pub fn expired(&self, now: DateTime<Utc>) -> u32 {
+        self.total() - self.usable(now)
+    }
+
+    /// All lots currently held, earliest expiry first
+    pub fn lots(&self) -> &[Lot] {
+        &self.lots
+    }
+
+    /// Consumes the given quantity first-expired-first-out, skipping expired lots.
+    /// Returns the usable quantity as error if there is not enough stock.
+    pub fn consume(&mut self, quantity: u32, now: DateTime<Utc>) -> Result<(), u32> {
+        self.take(quantity, now).map(|_| ())
+    }
+
+    /// Removes the given quantity first-expired-first-out and returns it as lots that keep
+    /// their original delivery and expiry dates, e.g. to move them to another machine.
+    pub fn take(&mut self, quantity: u32, now: DateTime<Utc>) -> Result<Vec<Lot>, u32> {
+        let usable = self.usable(now);
+        if usable < quantity {
+            return Err(usable);
+        }
+
+        let mut taken = Vec::new();
+        let mut remaining = quantity;
+        for lot in self.lots.iter_mut().filter(|l| !l.is_expired(now)) {
+            if remaining == 0 {
+                break;
+            }
+            let n = remaining.min(lot.quantity);
+            lot.quantity -= n;
+            remaining -= n;
+            taken.push(Lot {
+                quantity: n,
+                ..lot.clone()
+            });
+        }
+        // Drop lots that have been used up
+        self.lots.retain(|l| l.quantity > 0);
+        Ok(taken)
+    }
+
+    /// Removes all expired lots and returns them for reporting
+    pub fn write_off_expired(&mut self, now: DateTime<Utc>) -> Vec<Lot> {
+        let (expired, fresh): (Vec<Lot>, Vec<Lot>) =
+            self.lots.drain(..).partition(|l| l.is_expired(now));
+        self.lots = fresh;
+        expired
+    }
+}
@@ -0,0 +1,267 @@
+use crate::events::EventKind;
+use crate::ingredient::Ingredient;
+use crate::lots::LotStore;
+use crate::transaction::Transaction;
+use crate::{
+    ErrorResponse, FillResult, InventoryResponse, InventoryUpdate, SharedInventory, UpdateResponse,
+    bad_request,
+};
+use auth::{Principal, Role};
+use axum::{
+    Json,
+    extract::{Extension, Path},
+    http::{HeaderMap, StatusCode},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+use utoipa::ToSchema;
+
+/// Stock held by a single machine (its bean hopper and milk fridge)
+#[derive(Debug, Default)]
+pub struct MachineStock {
+    pub beans: u32,
+    pub milk: LotStore, // perishable, tracked per delivered lot
+    pub version: u64,   // incremented on every successful mutation, exposed as ETag
+}
+
+impl MachineStock {
+    /// Creates a machine with the given beans and one fresh milk lot
+    pub fn new(beans: u32, milk: u32, now: DateTime<Utc>, milk_expires_at: DateTime<Utc>) -> Self {
+        let mut stock = Self {
+            beans,
+            version: 1,
+            ..Default::default()
+        };
+        if milk > 0 {
+            stock.milk.receive(milk, now, milk_expires_at);
+        }
+        stock
+    }
+
+    /// Summarizes the stock of this machine
+    pub fn summary(&self, machine_id: &str, now: DateTime<Utc>) -> MachineSummary {
+        MachineSummary {
+            machine_id: machine_id.into(),
+            beans: self.beans,
+            milk: self.milk.usable(now),
+            milk_expired: self.milk.expired(now),
+            version: self.version,
+        }
+    }
+}
+
+/// Stock levels of a single machine
+#[derive(Serialize, ToSchema)]
+pub struct MachineSummary {
+    pub machine_id: String,
+    pub beans: u32,
+    pub milk: u32,         // usable (non-expired) milk
+    pub milk_expired: u32, // expired milk awaiting write-off
+    pub version: u64,
+}
+
+/// Response payload for GET /machines
+#[derive(Serialize, ToSchema)]
+pub struct AggregateResponse {
+    pub beans: u64,        // beans across all machines
+    pub milk: u64,         // usable milk across all machines
+    pub milk_expired: u64, // expired milk across all machines
+    pub machines: Vec<MachineSummary>,
+}
+
+/// Request payload for POST /transfers
+#[derive(Deserialize, ToSchema)]
+pub struct TransferRequest {
+    pub from: String, // machine giving the stock
+    pub to: String,   // machine receiving the stock
+    #[serde(default)]
+    pub beans: Option<u32>,
+    #[serde(default)]
+    pub milk: Option<u32>,
+}
+
+/// Response payload for POST /transfers
+#[derive(Serialize, ToSchema)]
+pub struct TransferResponse {
+    pub message: String,
+    pub from: MachineSummary,
+    pub to: MachineSummary,
+}
+
+/// Handler for GET /machines
+#[utoipa::path(
+    get,
+    path = "/machines",
+    tag = "Machines",
+    responses(
+        (status = 200, description = "Stock of every machine and the shop-wide totals", body = AggregateResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas and services may read the stock", body = ErrorResponse)
+    )
+)]
+pub async fn get_machines(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+) -> Result<Json<AggregateResponse>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Barista, Role::Service])?;
+    let inv = state.lock().await;
+    let now = Utc::now();
+    let machines: Vec<MachineSummary> = inv
+        .machines
+        .iter()
+        .map(|(id, stock)| stock.summary(id, now))
+        .collect();
+    Ok(Json(AggregateResponse {
+        beans: machines.iter().map(|m| u64::from(m.beans)).sum(),
+        milk: machines.iter().map(|m| u64::from(m.milk)).sum(),
+        milk_expired: machines.iter().map(|m| u64::from(m.milk_expired)).sum(),
+        machines,
+    }))
+}
+
+/// Handler for GET /machines/{id}/fill
+#[utoipa::path(
+    get,
+    path = "/machines/{id}/fill",
+    tag = "Machines",
+    params(("id" = String, Path, description = "Machine id")),
+    responses(
+        (status = 200, description = "Current inventory levels of the machine", body = InventoryResponse,
+            headers(("ETag" = String, description = "Current version of the machine's inventory"))),
+        (status = 404, description = "Unknown machine", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas and services may read the stock", body = ErrorResponse)
+    )
+)]
+pub async fn get_machine_fill(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+    Path(id): Path<String>,
+) -> FillResult<InventoryResponse> {
+    principal.require(&[Role::Barista, Role::Service])?;
+    let inv = state.lock().await;
+    crate::read_fill(&inv, &id)
+}
+
+/// Handler for PUT /machines/{id}/fill
+#[utoipa::path(
+    put,
+    path = "/machines/{id}/fill",
+    tag = "Machines",
+    request_body(content = InventoryUpdate, content_type = "application/json"),
+    params(
+        ("id" = String, Path, description = "Machine id"),
+        ("If-Match" = Option<String>, Header, description = "Only apply the update if the machine's inventory still has this ETag")
+    ),
+    responses(
+        (status = 200, description = "Inventory updated", body = UpdateResponse,
+            headers(("ETag" = String, description = "New version of the machine's inventory"))),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 404, description = "Unknown machine", body = ErrorResponse),
+        (status = 412, description = "Inventory was modified concurrently", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas may refill the stock", body = ErrorResponse)
+    )
+)]
+pub async fn put_machine_fill(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<InventoryUpdate>,
+) -> FillResult<UpdateResponse> {
+    principal.require(&[Role::Barista])?;
+    let mut inv = state.lock().await;
+    crate::refill(&mut inv, &id, &headers, payload)
+}
+
+/// Handler for DEL /machines/{id}/fill
+#[utoipa::path(
+    delete,
+    path = "/machines/{id}/fill",
+    tag = "Machines",
+    request_body(content = InventoryUpdate, content_type = "application/json"),
+    params(
+        ("id" = String, Path, description = "Machine id"),
+        ("If-Match" = Option<String>, Header, description = "Only apply the update if the machine's inventory still has this ETag")
+    ),
+    responses(
+        (status = 200, description = "Inventory updated", body = UpdateResponse,
+            headers(("ETag" = String, description = "New version of the machine's inventory"))),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 404, description = "Unknown machine", body = ErrorResponse),
+        (status = 412, description = "Inventory was modified concurrently", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only services may deduct stock", body = ErrorResponse)
+    )
+)]
+pub async fn del_machine_fill(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<InventoryUpdate>,
+) -> FillResult<UpdateResponse> {
+    principal.require(&[Role::Service])?;
+    let mut inv = state.lock().await;
+    crate::deduct(&mut inv, &id, &headers, payload)
+}
+
+/// Handler for POST /transfers
+#[utoipa::path(
+    post,
+    path = "/transfers",
+    tag = "Machines",
+    request_body(content = TransferRequest, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Stock moved between machines", body = TransferResponse),
+        (status = 400, description = "Invalid transfer or not enough stock", body = ErrorResponse),
+        (status = 404, description = "Unknown machine", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas may move stock", body = ErrorResponse)
+    )
+)]
+pub async fn post_transfer(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+    Json(payload): Json<TransferRequest>,
+) -> Result<Json<TransferResponse>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Barista])?;
+    let tx = Transaction::remove()
+        .with(Ingredient::Beans, payload.beans)
+        .with(Ingredient::Milk, payload.milk);
+    if tx.is_empty() {
+        return Err(bad_request("No values to transfer"));
+    }
+    if payload.from == payload.to {
+        return Err(bad_request("Cannot transfer to the same machine"));
+    }
+
+    // Validate both machines before moving any stock
+    let now = Utc::now();
+    let mut inv = state.lock().await;
+    let (source, target) = inv.machine_pair_mut(&payload.from, &payload.to)?;
+    tx.transfer(source, target, now)?;
+
+    let beans = tx.quantity(Ingredient::Beans);
+    let milk = tx.quantity(Ingredient::Milk);
+    info!(
+        "Transferred {} beans and {} milk from {} to {}",
+        beans, milk, payload.from, payload.to
+    );
+    inv.events.record(EventKind::StockTransferred {
+        from: payload.from.clone(),
+        to: payload.to.clone(),
+        beans,
+        milk,
+    });
+
+    let from = inv.machine(&payload.from)?.summary(&payload.from, now);
+    let to = inv.machine(&payload.to)?.summary(&payload.to, now);
+    Ok(Json(TransferResponse {
+        message: "Stock transferred".into(),
+        from,
+        to,
+    }))
+}
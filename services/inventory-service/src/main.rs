@@ -1,29 +1,120 @@
-use axum::{Json, Router, extract::Extension, http::StatusCode};
+mod config;
+mod events;
+mod forecast;
+mod health;
+mod ingredient;
+mod lots;
+mod machines;
+mod metrics;
+mod purchase_orders;
+mod transaction;
+
+use auth::{Principal, Role};
+use axum::{
+    Json, Router,
+    extract::Extension,
+    http::{HeaderMap, StatusCode, header},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use config::Config;
+use events::{EventKind, EventLog, InventoryEvent};
+use forecast::{ForecastResponse, Forecaster, IngredientForecast};
+use ingredient::Ingredient;
+use lots::Lot;
+use machines::{
+    AggregateResponse, MachineStock, MachineSummary, TransferRequest, TransferResponse,
+};
+use purchase_orders::{DeliveryRequest, PurchaseOrder, PurchaseOrderStatus, PurchaseOrders};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     net::{Ipv4Addr, SocketAddr},
     sync::Arc,
+    time::Duration,
 };
 use tokio::{net::TcpListener, sync::Mutex};
 use tracing::{info, warn};
+use transaction::{StockError, Transaction};
 use utoipa::{OpenApi, ToSchema};
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_swagger_ui::SwaggerUi;
 
 /// In-memory inventory state
-#[derive(Debug, Default)]
+#[derive(Debug)]
 struct Inventory {
-    beans: u32,
-    milk: u32,
+    machines: BTreeMap<String, MachineStock>, // stock per machine, keyed by machine id
+    default_machine: String,                  // machine addressed by the plain /fill endpoints
+    milk_shelf_life: chrono::Duration,        // default expiry for new milk lots
+    events: EventLog,                         // recent write-offs and other events
+    forecaster: Forecaster,                   // rolling deduction history for forecasts
+    purchase_orders: PurchaseOrders,          // supplier orders and reorder settings
+    metrics: metrics::Metrics,                // prometheus metrics, exposed on /metrics
+}
+
+impl Inventory {
+    /// Looks up the stock of a machine
+    fn machine(&self, id: &str) -> Result<&MachineStock, (StatusCode, Json<ErrorResponse>)> {
+        self.machines.get(id).ok_or_else(|| unknown_machine(id))
+    }
+
+    /// Looks up the stock of a machine for modification
+    fn machine_mut(
+        &mut self,
+        id: &str,
+    ) -> Result<&mut MachineStock, (StatusCode, Json<ErrorResponse>)> {
+        self.machines.get_mut(id).ok_or_else(|| unknown_machine(id))
+    }
+
+    /// Looks up the stocks of two different machines for modification
+    fn machine_pair_mut(
+        &mut self,
+        first: &str,
+        second: &str,
+    ) -> Result<(&mut MachineStock, &mut MachineStock), (StatusCode, Json<ErrorResponse>)> {
+        let (mut a, mut b) = (None, None);
+        for (id, stock) in self.machines.iter_mut() {
+            if id == first {
+                a = Some(stock);
+            } else if id == second {
+                b = Some(stock);
+            }
+        }
+        let a = a.ok_or_else(|| unknown_machine(first))?;
+        let b = b.ok_or_else(|| unknown_machine(second))?;
+        Ok((a, b))
+    }
+
+    /// Usable beans and milk across all machines, capped at `u32::MAX`, which is still above
+    /// any reorder point
+    fn usable_totals(&self, now: DateTime<Utc>) -> (u32, u32) {
+        self.machines.values().fold((0, 0), |(beans, milk), m| {
+            (
+                beans.saturating_add(m.beans),
+                milk.saturating_add(m.milk.usable(now)),
+            )
+        })
+    }
 }
 
 type SharedInventory = Arc<Mutex<Inventory>>;
 
+// Type alias for the result of the fill endpoints, a body with the ETag of the machine's stock
+type FillResult<T> = Result<
+    (StatusCode, [(header::HeaderName, String); 1], Json<T>),
+    (StatusCode, Json<ErrorResponse>),
+>;
+
 /// Response payload for GET /fill
 #[derive(Serialize, ToSchema)]
 struct InventoryResponse {
+    machine_id: String,
     beans: u32,
-    milk: u32,
+    milk: u32,         // usable (non-expired) milk
+    milk_expired: u32, // expired milk awaiting write-off
+    milk_lots: Vec<Lot>,
+    version: u64,
 }
 
 /// Request payload for PUT /fill
@@ -33,14 +124,19 @@ struct InventoryUpdate {
     beans: Option<u32>,
     #[serde(default)]
     milk: Option<u32>,
+    /// Expiry of the delivered milk lot (PUT only), defaults to the configured shelf life
+    #[serde(default)]
+    milk_expires_at: Option<DateTime<Utc>>,
 }
 
 /// Response for successful update
 #[derive(Serialize, ToSchema)]
 struct UpdateResponse {
     message: String,
+    machine_id: String,
     beans: u32,
     milk: u32,
+    version: u64,
 }
 
 /// Error response structure
@@ -49,45 +145,139 @@ struct ErrorResponse {
     error: String,
 }
 
+impl From<auth::AuthError> for ErrorResponse {
+    fn from(err: auth::AuthError) -> Self {
+        ErrorResponse {
+            error: err.to_string(),
+        }
+    }
+}
+
 /// OpenAPI documentation definition
 #[derive(OpenApi)]
 #[openapi(
     paths(
         get_fill,
-        put_fill
+        put_fill,
+        get_events,
+        get_forecast,
+        probes::get_live,
+        health::get_ready,
+        machines::get_machines,
+        machines::get_machine_fill,
+        machines::put_machine_fill,
+        machines::del_machine_fill,
+        machines::post_transfer,
+        purchase_orders::list_purchase_orders,
+        purchase_orders::place_purchase_order,
+        purchase_orders::deliver_purchase_order,
+        purchase_orders::cancel_purchase_order
     ),
     components(
         schemas(
             InventoryResponse,
             InventoryUpdate,
+            InventoryEvent,
+            EventKind,
+            Lot,
+            ForecastResponse,
+            IngredientForecast,
+            Ingredient,
+            PurchaseOrder,
+            PurchaseOrderStatus,
+            DeliveryRequest,
+            MachineSummary,
+            AggregateResponse,
+            TransferRequest,
+            TransferResponse,
             UpdateResponse,
-            ErrorResponse
+            ErrorResponse,
+            probes::HealthResponse,
+            probes::HealthStatus,
+            probes::Check
         )
     ),
     tags(
-        (name = "Inventory", description = "Inventory management API")
+        (name = "Inventory", description = "Inventory management API"),
+        (name = "Machines", description = "Per-machine inventory and transfers"),
+        (name = "Purchase Orders", description = "Supplier purchase order workflow"),
+        (name = "Health", description = "Liveness and readiness probes")
     )
 )]
 struct ApiDoc;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // init tracing
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    // load and validate the configuration from file, environment and command line
+    let cli = config::Cli::parse();
+    let config = Config::load(&cli)?;
+    if cli.print_config {
+        print!("{}", toml::to_string_pretty(&config.redacted())?);
+        return Ok(());
+    }
+
+    // init logging and optional OTLP span export
+    let tracer_provider = telemetry::init("inventory-service")?;
 
-    // initialize shared inventory
+    // initialize shared inventory, every machine starts with beans and one fresh milk lot
+    let default_machine = config.default_machine();
+    let milk_shelf_life = config.milk.shelf_life();
+    let now = Utc::now();
+    let milk_expires_at = milk_expiry(now, milk_shelf_life);
+    let machines = config
+        .machine_ids
+        .iter()
+        .cloned()
+        .map(|id| (id, MachineStock::new(20, 10, now, milk_expires_at)))
+        .collect();
     let shared_inventory = Arc::new(Mutex::new(Inventory {
-        beans: 20,
-        milk: 10,
+        machines,
+        default_machine,
+        milk_shelf_life,
+        events: EventLog::default(),
+        forecaster: Forecaster::new(config.forecast.window(), config.forecast.horizon()),
+        purchase_orders: PurchaseOrders::new(
+            config.reorder.beans,
+            config.reorder.milk,
+            config.reorder.lead_time(),
+        ),
+        metrics: metrics::Metrics::new()?,
     }));
 
+    // verifies bearer tokens and API keys, every caller is an admin while auth is disabled
+    let authenticator = Arc::new(auth::Authenticator::new(&config.auth)?);
+    if !config.auth.enabled {
+        warn!("Authentication is disabled, every caller may change the stock");
+    }
+
+    // periodically write off expired lots
+    tokio::spawn(write_off_expired_lots(
+        shared_inventory.clone(),
+        Duration::from_secs(config.milk.sweep_interval_secs),
+    ));
+
     // build OpenAPI router
     let (api_router, api_spec) = OpenApiRouter::with_openapi(ApiDoc::openapi())
         .routes(utoipa_axum::routes![get_fill])
         .routes(utoipa_axum::routes![put_fill])
         .routes(utoipa_axum::routes![del_fill])
+        .routes(utoipa_axum::routes![get_events])
+        .routes(utoipa_axum::routes![get_forecast])
+        .routes(utoipa_axum::routes![machines::get_machines])
+        .routes(utoipa_axum::routes![
+            machines::get_machine_fill,
+            machines::put_machine_fill,
+            machines::del_machine_fill
+        ])
+        .routes(utoipa_axum::routes![machines::post_transfer])
+        .routes(utoipa_axum::routes![purchase_orders::list_purchase_orders])
+        .routes(utoipa_axum::routes![purchase_orders::place_purchase_order])
+        .routes(utoipa_axum::routes![
+            purchase_orders::deliver_purchase_order
+        ])
+        .routes(utoipa_axum::routes![purchase_orders::cancel_purchase_order])
+        .routes(utoipa_axum::routes![probes::get_live])
+        .routes(utoipa_axum::routes![health::get_ready])
         .split_for_parts();
 
     // construct application
@@ -96,20 +286,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", api_spec))
         // mount API routes
         .merge(api_router)
+        // expose prometheus metrics
+        .route("/metrics", get(metrics::get_metrics))
+        // trace every request, continuing the caller's trace
+        .layer(axum::middleware::from_fn(telemetry::trace_request))
         // add shared inventory state
-        .layer(Extension(shared_inventory));
+        .layer(Extension(shared_inventory))
+        // identify callers for the role checks of the handlers
+        .layer(Extension(authenticator));
 
     // bind and run
-    let port: u16 = std::env::var("SERVICE_PORT")
-        .unwrap_or_else(|_| "8081".into())
-        .parse()?;
-    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.port));
     let listener = TcpListener::bind(&addr).await.unwrap();
     info!("Listening on {}", addr);
+    // Stop accepting requests on SIGINT or SIGTERM and let in-flight requests finish
     axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(signals::shutdown())
         .await
         .unwrap();
 
+    // flush spans that have not been exported yet
+    if let Some(Err(e)) = tracer_provider.map(|provider| provider.shutdown()) {
+        warn!("Flushing spans failed: {e}");
+    }
+    info!("Shutdown complete");
     Ok(())
 }
 
@@ -119,20 +319,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     path = "/fill",
     tag = "Inventory",
     responses(
-        (status = 200, description = "Current inventory levels", body = InventoryResponse)
+        (status = 200, description = "Current inventory levels of the default machine", body = InventoryResponse,
+            headers(("ETag" = String, description = "Current inventory version"))),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas and services may read the stock", body = ErrorResponse)
     )
 )]
 async fn get_fill(
+    principal: Principal,
     Extension(state): Extension<SharedInventory>,
-) -> (StatusCode, Json<InventoryResponse>) {
+) -> FillResult<InventoryResponse> {
+    principal.require(&[Role::Barista, Role::Service])?;
     let inv = state.lock().await;
-    (
-        StatusCode::OK,
-        Json(InventoryResponse {
-            beans: inv.beans,
-            milk: inv.milk,
-        }),
-    )
+    read_fill(&inv, &inv.default_machine)
 }
 
 /// Handler for PUT /fill
@@ -141,56 +340,28 @@ async fn get_fill(
     path = "/fill",
     tag = "Inventory",
     request_body(content = InventoryUpdate, content_type = "application/json"),
+    params(
+        ("If-Match" = Option<String>, Header, description = "Only apply the update if the inventory still has this ETag")
+    ),
     responses(
-        (status = 200, description = "Inventory updated", body = UpdateResponse),
-        (status = 400, description = "Invalid input", body = ErrorResponse)
+        (status = 200, description = "Inventory of the default machine updated", body = UpdateResponse,
+            headers(("ETag" = String, description = "New inventory version"))),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 412, description = "Inventory was modified concurrently", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas may refill the stock", body = ErrorResponse)
     )
 )]
 async fn put_fill(
+    principal: Principal,
     Extension(state): Extension<SharedInventory>,
+    headers: HeaderMap,
     Json(payload): Json<InventoryUpdate>,
-) -> Result<(StatusCode, Json<UpdateResponse>), (StatusCode, Json<ErrorResponse>)> {
-    // Validate and apply update
-    if payload.beans.unwrap_or(0) == 0 && payload.milk.unwrap_or(0) == 0 {
-        let err = ErrorResponse {
-            error: "No values to update".into(),
-        };
-        return Err((StatusCode::BAD_REQUEST, Json(err)));
-    }
-
+) -> FillResult<UpdateResponse> {
+    principal.require(&[Role::Barista])?;
     let mut inv = state.lock().await;
-    if let Some(b) = payload.beans {
-        inv.beans = inv.beans.checked_add(b).ok_or_else(|| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "Beans overflow".into(),
-                }),
-            )
-        })?;
-    }
-    if let Some(m) = payload.milk {
-        inv.milk = inv.milk.checked_add(m).ok_or_else(|| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "Milk overflow".into(),
-                }),
-            )
-        })?;
-    }
-
-    // Optional warning if low
-    if inv.beans < 2 {
-        warn!("Bean levels critically low: {} beans remaining", inv.beans);
-    }
-
-    let resp = UpdateResponse {
-        message: "Inventory updated".into(),
-        beans: inv.beans,
-        milk: inv.milk,
-    };
-    Ok((StatusCode::OK, Json(resp)))
+    let id = inv.default_machine.clone();
+    refill(&mut inv, &id, &headers, payload)
 }
 
 /// Handler for DEL /fill
@@ -199,59 +370,332 @@ async fn put_fill(
     path = "/fill",
     tag = "Inventory",
     request_body(content = InventoryUpdate, content_type = "application/json"),
+    params(
+        ("If-Match" = Option<String>, Header, description = "Only apply the update if the inventory still has this ETag")
+    ),
     responses(
-        (status = 200, description = "Inventory updated", body = UpdateResponse),
-        (status = 400, description = "Invalid input", body = ErrorResponse)
+        (status = 200, description = "Inventory of the default machine updated", body = UpdateResponse,
+            headers(("ETag" = String, description = "New inventory version"))),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 412, description = "Inventory was modified concurrently", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only services may deduct stock", body = ErrorResponse)
     )
 )]
 async fn del_fill(
+    principal: Principal,
     Extension(state): Extension<SharedInventory>,
+    headers: HeaderMap,
     Json(payload): Json<InventoryUpdate>,
-) -> Result<(StatusCode, Json<UpdateResponse>), (StatusCode, Json<ErrorResponse>)> {
-    // Validate and apply update
-    if payload.beans.unwrap_or(0) == 0 && payload.milk.unwrap_or(0) == 0 {
+) -> FillResult<UpdateResponse> {
+    principal.require(&[Role::Service])?;
+    let mut inv = state.lock().await;
+    let id = inv.default_machine.clone();
+    deduct(&mut inv, &id, &headers, payload)
+}
+
+/// Reports the inventory levels of a machine
+fn read_fill(inv: &Inventory, machine_id: &str) -> FillResult<InventoryResponse> {
+    let stock = inv.machine(machine_id)?;
+    let now = Utc::now();
+    Ok((
+        StatusCode::OK,
+        [(header::ETAG, etag(stock.version))],
+        Json(InventoryResponse {
+            machine_id: machine_id.into(),
+            beans: stock.beans,
+            milk: stock.milk.usable(now),
+            milk_expired: stock.milk.expired(now),
+            milk_lots: stock.milk.lots().to_vec(),
+            version: stock.version,
+        }),
+    ))
+}
+
+/// Expiry of a milk lot refilled at `now` without its own, the latest date if out of range
+fn milk_expiry(now: DateTime<Utc>, shelf_life: chrono::Duration) -> DateTime<Utc> {
+    now.checked_add_signed(shelf_life)
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+/// Adds ingredients to the stock of a machine
+fn refill(
+    inv: &mut Inventory,
+    machine_id: &str,
+    headers: &HeaderMap,
+    payload: InventoryUpdate,
+) -> FillResult<UpdateResponse> {
+    let now = Utc::now();
+    let milk_expires_at = payload
+        .milk_expires_at
+        .unwrap_or_else(|| milk_expiry(now, inv.milk_shelf_life));
+    let tx = Transaction::add(milk_expires_at)
+        .with(Ingredient::Beans, payload.beans)
+        .with(Ingredient::Milk, payload.milk);
+    if tx.is_empty() {
         let err = ErrorResponse {
             error: "No values to update".into(),
         };
         return Err((StatusCode::BAD_REQUEST, Json(err)));
     }
 
-    let mut inv = state.lock().await;
-    if let Some(b) = payload.beans {
-        inv.beans = inv.beans.checked_sub(b).ok_or_else(|| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "Beans underflow".into(),
-                }),
-            )
-        })?;
+    // Validate all ingredients first, then apply them together
+    let stock = inv.machine_mut(machine_id)?;
+    check_if_match(headers, stock.version)?;
+    tx.apply(stock, now)?;
+
+    // Optional warning if low
+    if stock.beans < 2 {
+        warn!(
+            "Bean levels critically low on {}: {} beans remaining",
+            machine_id, stock.beans
+        );
     }
-    if let Some(m) = payload.milk {
-        inv.milk = inv.milk.checked_sub(m).ok_or_else(|| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "Milk underflow".into(),
-                }),
-            )
-        })?;
+
+    let resp = UpdateResponse {
+        message: "Inventory updated".into(),
+        machine_id: machine_id.into(),
+        beans: stock.beans,
+        milk: stock.milk.usable(now),
+        version: stock.version,
+    };
+    inv.metrics.record_refill(machine_id, &tx);
+
+    Ok((
+        StatusCode::OK,
+        [(header::ETAG, etag(resp.version))],
+        Json(resp),
+    ))
+}
+
+/// Deducts ingredients from the stock of a machine
+fn deduct(
+    inv: &mut Inventory,
+    machine_id: &str,
+    headers: &HeaderMap,
+    payload: InventoryUpdate,
+) -> FillResult<UpdateResponse> {
+    let tx = Transaction::remove()
+        .with(Ingredient::Beans, payload.beans)
+        .with(Ingredient::Milk, payload.milk);
+    if tx.is_empty() {
+        let err = ErrorResponse {
+            error: "No values to update".into(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(err)));
+    }
+
+    // Validate all ingredients first, then apply them together
+    let now = Utc::now();
+    let stock = inv.machine_mut(machine_id)?;
+    check_if_match(headers, stock.version)?;
+    if let Err(e) = tx.apply(stock, now) {
+        if let StockError::Underflow(ingredient) = e {
+            inv.metrics.record_underflow(machine_id, ingredient);
+        }
+        return Err(e.into());
     }
 
     // Optional warning if low
-    if inv.beans < 2 {
-        warn!("Bean levels critically low: {} beans remaining", inv.beans);
+    if stock.beans < 2 {
+        warn!(
+            "Bean levels critically low on {}: {} beans remaining",
+            machine_id, stock.beans
+        );
     }
 
     // Optional warning if low
-    if inv.milk < 2 {
-        warn!("Milk levels critically low: {} milk remaining", inv.milk);
+    if stock.milk.usable(now) < 2 {
+        warn!(
+            "Milk levels critically low on {}: {} milk remaining",
+            machine_id,
+            stock.milk.usable(now)
+        );
     }
 
     let resp = UpdateResponse {
         message: "Inventory updated".into(),
-        beans: inv.beans,
-        milk: inv.milk,
+        machine_id: machine_id.into(),
+        beans: stock.beans,
+        milk: stock.milk.usable(now),
+        version: stock.version,
+    };
+
+    // Remember the deduction for consumption forecasts
+    inv.forecaster.record(
+        tx.quantity(Ingredient::Beans),
+        tx.quantity(Ingredient::Milk),
+        now,
+    );
+    draft_purchase_orders(inv, now);
+
+    Ok((
+        StatusCode::OK,
+        [(header::ETAG, etag(resp.version))],
+        Json(resp),
+    ))
+}
+
+/// Handler for GET /inventory/events
+#[utoipa::path(
+    get,
+    path = "/inventory/events",
+    tag = "Inventory",
+    responses(
+        (status = 200, description = "Recent inventory events, newest first", body = Vec<InventoryEvent>),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas and services may read the stock", body = ErrorResponse)
+    )
+)]
+async fn get_events(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+) -> Result<Json<Vec<InventoryEvent>>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Barista, Role::Service])?;
+    let inv = state.lock().await;
+    Ok(Json(inv.events.recent()))
+}
+
+/// Handler for GET /inventory/forecast
+#[utoipa::path(
+    get,
+    path = "/inventory/forecast",
+    tag = "Inventory",
+    responses(
+        (status = 200, description = "Consumption rates, time-to-empty and refill suggestions", body = ForecastResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas and services may read the stock", body = ErrorResponse)
+    )
+)]
+async fn get_forecast(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+) -> Result<Json<ForecastResponse>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Barista, Role::Service])?;
+    let mut inv = state.lock().await;
+    let now = Utc::now();
+    let (beans, milk) = inv.usable_totals(now);
+    Ok(Json(inv.forecaster.forecast(beans, milk, now)))
+}
+
+/// Periodically removes expired lots from stock and records a write-off event for each
+async fn write_off_expired_lots(state: SharedInventory, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+
+        let now = Utc::now();
+        let mut inv = state.lock().await;
+        let inv = &mut *inv;
+        let mut written_off = false;
+
+        for (machine_id, stock) in inv.machines.iter_mut() {
+            let expired = stock.milk.write_off_expired(now);
+            if expired.is_empty() {
+                continue;
+            }
+
+            for lot in expired {
+                warn!(
+                    "Writing off expired milk lot {} on {} ({} units, expired {})",
+                    lot.id, machine_id, lot.quantity, lot.expires_at
+                );
+                inv.events.record(EventKind::LotWrittenOff {
+                    machine_id: machine_id.clone(),
+                    ingredient: Ingredient::Milk,
+                    lot_id: lot.id,
+                    quantity: lot.quantity,
+                    expired_at: lot.expires_at,
+                });
+            }
+            stock.version += 1;
+            written_off = true;
+        }
+
+        if written_off {
+            draft_purchase_orders(inv, now);
+        }
+    }
+}
+
+/// Drafts purchase orders for ingredients that reached their reorder point
+fn draft_purchase_orders(inv: &mut Inventory, now: DateTime<Utc>) {
+    let (beans, milk) = inv.usable_totals(now);
+    let forecast = inv.forecaster.forecast(beans, milk, now);
+    for f in forecast.ingredients {
+        if let Some(po) = inv.purchase_orders.draft_if_needed(
+            f.ingredient,
+            f.stock,
+            f.hours_to_empty,
+            f.suggested_refill,
+            now,
+        ) {
+            info!(
+                "Drafted purchase order {} for {} {}",
+                po.id, po.quantity, po.ingredient
+            );
+            inv.events.record(EventKind::PurchaseOrderDrafted {
+                purchase_order_id: po.id,
+                ingredient: po.ingredient,
+                quantity: po.quantity,
+            });
+        }
+    }
+}
+
+/// Builds a 400 Bad Request error response
+fn bad_request(error: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: error.into(),
+        }),
+    )
+}
+
+/// Builds a 404 Not Found error response for an unknown machine
+fn unknown_machine(id: &str) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: format!("Unknown machine {}", id),
+        }),
+    )
+}
+
+/// Formats an inventory version as a strong ETag value
+fn etag(version: u64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Validates an optional If-Match header against the current version of a machine's inventory
+fn check_if_match(
+    headers: &HeaderMap,
+    version: u64,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        // No precondition given, update unconditionally
+        return Ok(());
     };
-    Ok((StatusCode::OK, Json(resp)))
+
+    let current = etag(version);
+    let matches = value
+        .to_str()
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .any(|t| t == "*" || t == current)
+        })
+        .unwrap_or(false);
+
+    if matches {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::PRECONDITION_FAILED,
+            Json(ErrorResponse {
+                error: format!("Inventory has been modified, current ETag is {}", current),
+            }),
+        ))
+    }
 }
@@ -0,0 +1,117 @@
+use crate::SharedInventory;
+use crate::ingredient::Ingredient;
+use crate::transaction::Transaction;
+use axum::{extract::Extension, http::header, response::IntoResponse};
+use chrono::Utc;
+use prometheus::{Encoder, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::fmt;
+
+/// Prometheus metrics of the inventory service
+pub struct Metrics {
+    registry: Registry,        // Registry all metrics below belong to
+    stock: IntGaugeVec,        // Usable stock by machine and ingredient, set on scrape
+    expired: IntGaugeVec,      // Expired milk awaiting write-off, by machine
+    refills: IntCounterVec,    // Successful refills, by machine and ingredient
+    underflows: IntCounterVec, // Deductions rejected for lack of stock
+}
+
+impl Metrics {
+    /// Creates and registers all metrics
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let stock = IntGaugeVec::new(
+            Opts::new("inventory_stock", "Usable stock of an ingredient"),
+            &["machine", "ingredient"],
+        )?;
+        let expired = IntGaugeVec::new(
+            Opts::new("inventory_milk_expired", "Expired milk awaiting write-off"),
+            &["machine"],
+        )?;
+        let refills = IntCounterVec::new(
+            Opts::new("inventory_refills_total", "Refills of an ingredient"),
+            &["machine", "ingredient"],
+        )?;
+        let underflows = IntCounterVec::new(
+            Opts::new(
+                "inventory_underflow_errors_total",
+                "Deductions rejected because of insufficient stock",
+            ),
+            &["machine", "ingredient"],
+        )?;
+        registry.register(Box::new(stock.clone()))?;
+        registry.register(Box::new(expired.clone()))?;
+        registry.register(Box::new(refills.clone()))?;
+        registry.register(Box::new(underflows.clone()))?;
+
+        Ok(Self {
+            registry,
+            stock,
+            expired,
+            refills,
+            underflows,
+        })
+    }
+
+    /// Counts a refill of every ingredient in the transaction
+    pub fn record_refill(&self, machine_id: &str, tx: &Transaction) {
+        for ingredient in [Ingredient::Beans, Ingredient::Milk] {
+            if tx.quantity(ingredient) > 0 {
+                self.refills
+                    .with_label_values(&[machine_id, &ingredient.to_string()])
+                    .inc();
+            }
+        }
+    }
+
+    /// Counts a deduction rejected for lack of an ingredient
+    pub fn record_underflow(&self, machine_id: &str, ingredient: Ingredient) {
+        self.underflows
+            .with_label_values(&[machine_id, &ingredient.to_string()])
+            .inc();
+    }
+
+    /// Encodes all metrics in the Prometheus text format
+    fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!(error=%e, "Failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+/// Handler for GET /metrics, scraped by Prometheus
+pub async fn get_metrics(Extension(state): Extension<SharedInventory>) -> impl IntoResponse {
+    let inv = state.lock().await;
+    let now = Utc::now();
+
+    // Stock gauges are taken from the current inventory on every scrape
+    for (machine_id, stock) in &inv.machines {
+        inv.metrics
+            .stock
+            .with_label_values(&[machine_id.as_str(), "beans"])
+            .set(stock.beans.into());
+        inv.metrics
+            .stock
+            .with_label_values(&[machine_id.as_str(), "milk"])
+            .set(stock.milk.usable(now).into());
+        inv.metrics
+            .expired
+            .with_label_values(&[machine_id.as_str()])
+            .set(stock.milk.expired(now).into());
+    }
+
+    (
+        [(
+            header::CONTENT_TYPE,
+            TextEncoder::new().format_type().to_string(),
+        )],
+        inv.metrics.render(),
+    )
+}
@@ -0,0 +1,358 @@
+use crate::events::EventKind;
+use crate::ingredient::Ingredient;
+use crate::transaction::Transaction;
+use crate::{ErrorResponse, SharedInventory, milk_expiry};
+use auth::{Principal, Role};
+use axum::{
+    Json,
+    extract::{Extension, Path},
+    http::StatusCode,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+use utoipa::ToSchema;
+
+/// Lifecycle of a purchase order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PurchaseOrderStatus {
+    Draft,     // Suggested by the reorder check, not yet sent to the supplier
+    Ordered,   // Placed with the supplier, waiting for delivery
+    Delivered, // Received and added to the inventory
+    Cancelled, // Dropped before delivery
+}
+
+/// A purchase order for a single ingredient
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PurchaseOrder {
+    pub id: u64,
+    pub ingredient: Ingredient,
+    pub quantity: u32,                  // Quantity ordered from the supplier
+    pub received_quantity: Option<u32>, // Quantity actually delivered
+    pub status: PurchaseOrderStatus,
+    pub created_at: DateTime<Utc>,
+    pub ordered_at: Option<DateTime<Utc>>,
+    pub expected_at: Option<DateTime<Utc>>, // ordered_at plus supplier lead time
+    pub delivered_at: Option<DateTime<Utc>>,
+}
+
+/// Reorder settings for a single ingredient
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReorderPolicy {
+    pub reorder_point: u32,    // Draft an order once stock drops to this level
+    pub reorder_quantity: u32, // Minimum quantity to order
+}
+
+/// Errors that can occur when changing a purchase order
+#[derive(Debug, Error)]
+pub enum PurchaseOrderError {
+    #[error("Purchase order {0} not found")]
+    NotFound(u64),
+    #[error("Purchase order {id} cannot move from {from:?} to {to:?}")]
+    InvalidTransition {
+        id: u64,
+        from: PurchaseOrderStatus,
+        to: PurchaseOrderStatus,
+    },
+}
+
+impl From<PurchaseOrderError> for (StatusCode, Json<ErrorResponse>) {
+    fn from(err: PurchaseOrderError) -> Self {
+        let status = match err {
+            PurchaseOrderError::NotFound(_) => StatusCode::NOT_FOUND,
+            PurchaseOrderError::InvalidTransition { .. } => StatusCode::CONFLICT,
+        };
+        (
+            status,
+            Json(ErrorResponse {
+                error: err.to_string(),
+            }),
+        )
+    }
+}
+
+/// All purchase orders together with the reorder configuration
+#[derive(Debug)]
+pub struct PurchaseOrders {
+    orders: Vec<PurchaseOrder>,
+    next_id: u64,
+    beans: ReorderPolicy,
+    milk: ReorderPolicy,
+    lead_time: chrono::Duration, // Time between ordering and delivery
+}
+
+impl PurchaseOrders {
+    /// Creates an empty purchase order book
+    pub fn new(beans: ReorderPolicy, milk: ReorderPolicy, lead_time: chrono::Duration) -> Self {
+        Self {
+            orders: Vec::new(),
+            next_id: 0,
+            beans,
+            milk,
+            lead_time,
+        }
+    }
+
+    /// All purchase orders, oldest first
+    pub fn all(&self) -> &[PurchaseOrder] {
+        &self.orders
+    }
+
+    /// Drafts a purchase order if the stock is at its reorder point or would run out
+    /// before a new delivery could arrive. Only one open order per ingredient is kept.
+    pub fn draft_if_needed(
+        &mut self,
+        ingredient: Ingredient,
+        stock: u32,
+        hours_to_empty: Option<f64>,
+        suggested_refill: u32,
+        now: DateTime<Utc>,
+    ) -> Option<&PurchaseOrder> {
+        let has_open = self.orders.iter().any(|po| {
+            po.ingredient == ingredient
+                && matches!(
+                    po.status,
+                    PurchaseOrderStatus::Draft | PurchaseOrderStatus::Ordered
+                )
+        });
+        if has_open {
+            return None;
+        }
+
+        let policy = match ingredient {
+            Ingredient::Beans => self.beans,
+            Ingredient::Milk => self.milk,
+        };
+        let lead_hours = self.lead_time.num_minutes() as f64 / 60.0;
+        let runs_out_before_delivery = hours_to_empty.is_some_and(|h| h <= lead_hours);
+        if stock > policy.reorder_point && !runs_out_before_delivery {
+            return None;
+        }
+
+        self.next_id += 1;
+        self.orders.push(PurchaseOrder {
+            id: self.next_id,
+            ingredient,
+            quantity: policy.reorder_quantity.max(suggested_refill),
+            received_quantity: None,
+            status: PurchaseOrderStatus::Draft,
+            created_at: now,
+            ordered_at: None,
+            expected_at: None,
+            delivered_at: None,
+        });
+        self.orders.last()
+    }
+
+    /// Looks up a purchase order and checks it may move to the given status
+    pub fn check_transition(
+        &self,
+        id: u64,
+        to: PurchaseOrderStatus,
+    ) -> Result<&PurchaseOrder, PurchaseOrderError> {
+        let po = self
+            .orders
+            .iter()
+            .find(|po| po.id == id)
+            .ok_or(PurchaseOrderError::NotFound(id))?;
+
+        let allowed = matches!(
+            (po.status, to),
+            (PurchaseOrderStatus::Draft, PurchaseOrderStatus::Ordered)
+                | (PurchaseOrderStatus::Ordered, PurchaseOrderStatus::Delivered)
+                | (PurchaseOrderStatus::Draft, PurchaseOrderStatus::Cancelled)
+                | (PurchaseOrderStatus::Ordered, PurchaseOrderStatus::Cancelled)
+        );
+        if !allowed {
+            return Err(PurchaseOrderError::InvalidTransition {
+                id,
+                from: po.status,
+                to,
+            });
+        }
+        Ok(po)
+    }
+
+    /// Moves a purchase order to a new status and stamps the matching timestamps
+    pub fn transition(
+        &mut self,
+        id: u64,
+        to: PurchaseOrderStatus,
+        received_quantity: Option<u32>,
+        now: DateTime<Utc>,
+    ) -> Result<&PurchaseOrder, PurchaseOrderError> {
+        self.check_transition(id, to)?;
+        let lead_time = self.lead_time;
+        let po = self
+            .orders
+            .iter_mut()
+            .find(|po| po.id == id)
+            .ok_or(PurchaseOrderError::NotFound(id))?;
+
+        po.status = to;
+        match to {
+            PurchaseOrderStatus::Ordered => {
+                po.ordered_at = Some(now);
+                po.expected_at = Some(now + lead_time);
+            }
+            PurchaseOrderStatus::Delivered => {
+                po.delivered_at = Some(now);
+                po.received_quantity = received_quantity;
+            }
+            PurchaseOrderStatus::Draft | PurchaseOrderStatus::Cancelled => {}
+        }
+        Ok(po)
+    }
+}
+
+/// Request payload for POST /purchase-orders/{id}/deliver
+#[derive(Deserialize, ToSchema)]
+pub struct DeliveryRequest {
+    /// Quantity actually received, defaults to the ordered quantity
+    #[serde(default)]
+    pub received_quantity: Option<u32>,
+    /// Expiry of the delivered milk lot, defaults to the configured shelf life
+    #[serde(default)]
+    pub milk_expires_at: Option<DateTime<Utc>>,
+    /// Machine receiving the delivery, defaults to the default machine
+    #[serde(default)]
+    pub machine_id: Option<String>,
+}
+
+/// Handler for GET /purchase-orders
+#[utoipa::path(
+    get,
+    path = "/purchase-orders",
+    tag = "Purchase Orders",
+    responses(
+        (status = 200, description = "All purchase orders, oldest first", body = Vec<PurchaseOrder>),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas and services may read purchase orders", body = ErrorResponse)
+    )
+)]
+pub async fn list_purchase_orders(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+) -> Result<Json<Vec<PurchaseOrder>>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Barista, Role::Service])?;
+    let inv = state.lock().await;
+    Ok(Json(inv.purchase_orders.all().to_vec()))
+}
+
+/// Handler for POST /purchase-orders/{id}/order
+#[utoipa::path(
+    post,
+    path = "/purchase-orders/{id}/order",
+    tag = "Purchase Orders",
+    params(("id" = u64, Path, description = "Purchase order id")),
+    responses(
+        (status = 200, description = "Purchase order placed with the supplier", body = PurchaseOrder),
+        (status = 404, description = "Purchase order not found", body = ErrorResponse),
+        (status = 409, description = "Purchase order is not a draft", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only admins may place or cancel purchase orders", body = ErrorResponse)
+    )
+)]
+pub async fn place_purchase_order(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+    Path(id): Path<u64>,
+) -> Result<Json<PurchaseOrder>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Admin])?;
+    let mut inv = state.lock().await;
+    let po = inv
+        .purchase_orders
+        .transition(id, PurchaseOrderStatus::Ordered, None, Utc::now())?;
+    info!(
+        "Purchase order {} for {} {} placed",
+        po.id, po.quantity, po.ingredient
+    );
+    Ok(Json(po.clone()))
+}
+
+/// Handler for POST /purchase-orders/{id}/deliver
+#[utoipa::path(
+    post,
+    path = "/purchase-orders/{id}/deliver",
+    tag = "Purchase Orders",
+    params(("id" = u64, Path, description = "Purchase order id")),
+    request_body(content = DeliveryRequest, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Delivery received and added to the inventory", body = PurchaseOrder),
+        (status = 400, description = "Delivery cannot be added to the inventory", body = ErrorResponse),
+        (status = 404, description = "Purchase order or machine not found", body = ErrorResponse),
+        (status = 409, description = "Purchase order has not been placed", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas may receive deliveries", body = ErrorResponse)
+    )
+)]
+pub async fn deliver_purchase_order(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+    Path(id): Path<u64>,
+    Json(payload): Json<DeliveryRequest>,
+) -> Result<Json<PurchaseOrder>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Barista])?;
+    let now = Utc::now();
+    let mut inv = state.lock().await;
+
+    // Validate the transition before touching the stock
+    let po = inv
+        .purchase_orders
+        .check_transition(id, PurchaseOrderStatus::Delivered)?
+        .clone();
+    let received = payload.received_quantity.unwrap_or(po.quantity);
+    let machine_id = payload
+        .machine_id
+        .unwrap_or_else(|| inv.default_machine.clone());
+    let milk_expires_at = payload
+        .milk_expires_at
+        .unwrap_or_else(|| milk_expiry(now, inv.milk_shelf_life));
+
+    let stock = inv.machine_mut(&machine_id)?;
+    let tx = Transaction::add(milk_expires_at).with(po.ingredient, Some(received));
+    tx.apply(stock, now)?;
+    inv.metrics.record_refill(&machine_id, &tx);
+
+    let po = inv
+        .purchase_orders
+        .transition(id, PurchaseOrderStatus::Delivered, Some(received), now)?
+        .clone();
+    inv.events.record(EventKind::PurchaseOrderDelivered {
+        purchase_order_id: po.id,
+        ingredient: po.ingredient,
+        machine_id,
+        received_quantity: received,
+    });
+    Ok(Json(po))
+}
+
+/// Handler for POST /purchase-orders/{id}/cancel
+#[utoipa::path(
+    post,
+    path = "/purchase-orders/{id}/cancel",
+    tag = "Purchase Orders",
+    params(("id" = u64, Path, description = "Purchase order id")),
+    responses(
+        (status = 200, description = "Purchase order cancelled", body = PurchaseOrder),
+        (status = 404, description = "Purchase order not found", body = ErrorResponse),
+        (status = 409, description = "Purchase order was already delivered or cancelled", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only admins may place or cancel purchase orders", body = ErrorResponse)
+    )
+)]
+pub async fn cancel_purchase_order(
+    principal: Principal,
+    Extension(state): Extension<SharedInventory>,
+    Path(id): Path<u64>,
+) -> Result<Json<PurchaseOrder>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Admin])?;
+    let mut inv = state.lock().await;
+    let po =
+        inv.purchase_orders
+            .transition(id, PurchaseOrderStatus::Cancelled, None, Utc::now())?;
+    Ok(Json(po.clone()))
+}
@@ -0,0 +1,195 @@
+use crate::ErrorResponse;
+use crate::ingredient::Ingredient;
+use crate::machines::MachineStock;
+use axum::{Json, http::StatusCode};
+use chrono::{DateTime, Utc};
+use thiserror::Error;
+use tracing::info;
+
+/// Errors that prevent a transaction from being applied
+#[derive(Debug, Error)]
+pub enum StockError {
+    #[error("{} overflow", .0.title())]
+    Overflow(Ingredient),
+    #[error("{} underflow", .0.title())]
+    Underflow(Ingredient),
+    #[error("Milk lot is already expired")]
+    Expired,
+}
+
+impl From<StockError> for (StatusCode, Json<ErrorResponse>) {
+    fn from(err: StockError) -> Self {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: err.to_string(),
+            }),
+        )
+    }
+}
+
+/// Whether a transaction adds to or removes from the stock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Add,
+    Remove,
+}
+
+/// A multi-ingredient change to a machine's stock that is applied all-or-nothing:
+/// every line is validated against the current stock before any of them is applied.
+#[derive(Debug)]
+pub struct Transaction {
+    direction: Direction,
+    lines: Vec<(Ingredient, u32)>,
+    milk_expires_at: Option<DateTime<Utc>>, // expiry of a milk lot being added
+}
+
+impl Transaction {
+    /// Starts a transaction that adds stock, milk is received as a lot with the given expiry
+    pub fn add(milk_expires_at: DateTime<Utc>) -> Self {
+        Self {
+            direction: Direction::Add,
+            lines: Vec::new(),
+            milk_expires_at: Some(milk_expires_at),
+        }
+    }
+
+    /// Starts a transaction that removes stock
+    pub fn remove() -> Self {
+        Self {
+            direction: Direction::Remove,
+            lines: Vec::new(),
+            milk_expires_at: None,
+        }
+    }
+
+    /// Adds a line for an ingredient, missing or zero quantities are skipped
+    pub fn with(mut self, ingredient: Ingredient, quantity: Option<u32>) -> Self {
+        if let Some(q) = quantity.filter(|q| *q > 0) {
+            self.lines.push((ingredient, q));
+        }
+        self
+    }
+
+    /// Returns true if the transaction would not change anything
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Quantity of an ingredient in this transaction
+    pub fn quantity(&self, ingredient: Ingredient) -> u32 {
+        self.lines
+            .iter()
+            .filter(|(i, _)| *i == ingredient)
+            .map(|(_, q)| q)
+            .sum()
+    }
+
+    /// Checks every line against the stock without changing it
+    pub fn validate(&self, stock: &MachineStock, now: DateTime<Utc>) -> Result<(), StockError> {
+        for ingredient in [Ingredient::Beans, Ingredient::Milk] {
+            let quantity = self.quantity(ingredient);
+            if quantity == 0 {
+                continue;
+            }
+
+            let (current, usable) = match ingredient {
+                Ingredient::Beans => (stock.beans, stock.beans),
+                Ingredient::Milk => (stock.milk.total(), stock.milk.usable(now)),
+            };
+            match self.direction {
+                Direction::Add => {
+                    if ingredient == Ingredient::Milk
+                        && self.milk_expires_at.is_some_and(|e| e <= now)
+                    {
+                        return Err(StockError::Expired);
+                    }
+                    current
+                        .checked_add(quantity)
+                        .ok_or(StockError::Overflow(ingredient))?;
+                }
+                Direction::Remove => {
+                    // Expired milk is never used
+                    usable
+                        .checked_sub(quantity)
+                        .ok_or(StockError::Underflow(ingredient))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validates the whole transaction and then applies it, either every line is applied or none
+    pub fn apply(&self, stock: &mut MachineStock, now: DateTime<Utc>) -> Result<(), StockError> {
+        self.validate(stock, now)?;
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let beans = self.quantity(Ingredient::Beans);
+        let milk = self.quantity(Ingredient::Milk);
+        match self.direction {
+            Direction::Add => {
+                stock.beans += beans;
+                if milk > 0 {
+                    let expires_at = self.milk_expires_at.ok_or(StockError::Expired)?;
+                    let lot = stock.milk.receive(milk, now, expires_at);
+                    info!(
+                        "Received milk lot {} ({} units, expires {})",
+                        lot.id, lot.quantity, lot.expires_at
+                    );
+                }
+            }
+            Direction::Remove => {
+                stock.beans -= beans;
+                // Consume first-expiring lots first
+                stock
+                    .milk
+                    .consume(milk, now)
+                    .map_err(|_| StockError::Underflow(Ingredient::Milk))?;
+            }
+        }
+        stock.version += 1;
+        Ok(())
+    }
+
+    /// Moves the lines of a removing transaction from `source` to `target`, both machines are
+    /// validated before either is changed and moved milk keeps the dates of its lots
+    pub fn transfer(
+        &self,
+        source: &mut MachineStock,
+        target: &mut MachineStock,
+        now: DateTime<Utc>,
+    ) -> Result<(), StockError> {
+        self.validate(source, now)?;
+        for (ingredient, current) in [
+            (Ingredient::Beans, target.beans),
+            (Ingredient::Milk, target.milk.total()),
+        ] {
+            current
+                .checked_add(self.quantity(ingredient))
+                .ok_or(StockError::Overflow(ingredient))?;
+        }
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        // Take from the source first-expired-first-out, lots keep their dates
+        let beans = self.quantity(Ingredient::Beans);
+        source.beans -= beans;
+        let lots = source
+            .milk
+            .take(self.quantity(Ingredient::Milk), now)
+            .map_err(|_| StockError::Underflow(Ingredient::Milk))?;
+        source.version += 1;
+
+        target.beans += beans;
+        for lot in lots {
+            target
+                .milk
+                .receive(lot.quantity, lot.delivered_at, lot.expires_at);
+        }
+        target.version += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use futures_util::future::BoxFuture;
+use std::time::Duration;
+
+/// Slowest time scale apart from 0, slower scales would overflow the simulated durations
+const MIN_TIME_SCALE: f64 = 0.01;
+
+/// Source of time for the brewing simulation, injectable so tests and demos can run faster
+pub trait Clock: Send + Sync {
+    /// Current wall-clock time used for timestamps
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Waits for a simulated duration
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Clock running simulated durations faster (or slower) than real time
+pub struct ScaledClock {
+    time_scale: f64, // 1 = real time, 10 = ten times faster, 0 = instant
+}
+
+impl ScaledClock {
+    /// Creates a clock with the given time scale, which must be 0 or at least `MIN_TIME_SCALE`
+    pub fn new(time_scale: f64) -> Result<Self, String> {
+        if !time_scale.is_finite() || (time_scale != 0.0 && time_scale < MIN_TIME_SCALE) {
+            return Err(format!(
+                "time_scale must be 0 (instant) or at least {}",
+                MIN_TIME_SCALE
+            ));
+        }
+        Ok(Self { time_scale })
+    }
+}
+
+impl Clock for ScaledClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        if self.time_scale == 0.0 {
+            // Instant mode still yields, so other units and handlers get a turn
+            return Box::pin(tokio::task::yield_now());
+        }
+        // Durations too long for a Duration are as good as forever
+        let scaled = Duration::try_from_secs_f64(duration.as_secs_f64() / self.time_scale)
+            .unwrap_or(Duration::MAX);
+        Box::pin(tokio::time::sleep(scaled))
+    }
+}
@@ -0,0 +1,168 @@
+use crate::ErrorResponse;
+use crate::machine::Phase;
+use crate::status::StatusState;
+use auth::{Principal, Role};
+use axum::{Extension, Json, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// Largest slow brew factor, far slower factors would overflow the phase durations
+const MAX_SLOW_BREW_FACTOR: f64 = 100.0;
+
+/// Simulated hardware error that makes a brew fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum Fault {
+    #[error("Grinder jammed")]
+    GrinderJam,
+    #[error("Milk frother failed")]
+    FrotherFailure,
+    #[error("Brew failed")]
+    BrewFailure,
+}
+
+/// Faults injected into the brewing simulation
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct FaultConfig {
+    pub grinder_jam: bool,        // Every brew fails while grinding
+    pub frother_failure: bool,    // Every milk drink fails while frothing
+    pub failure_probability: f64, // Chance (0-1) that a brew fails while brewing
+    pub slow_brew_factor: f64,    // Every phase takes this many times longer (1 to 100)
+}
+
+impl Default for FaultConfig {
+    fn default() -> Self {
+        Self {
+            grinder_jam: false,
+            frother_failure: false,
+            failure_probability: 0.0,
+            slow_brew_factor: 1.0,
+        }
+    }
+}
+
+impl FaultConfig {
+    /// Checks the configured values are in range
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.failure_probability) {
+            return Err("failure_probability must be between 0 and 1".into());
+        }
+        if !(1.0..=MAX_SLOW_BREW_FACTOR).contains(&self.slow_brew_factor) {
+            return Err(format!(
+                "slow_brew_factor must be between 1 and {}",
+                MAX_SLOW_BREW_FACTOR
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the fault that hits a brew at the end of the given phase, if any
+    pub fn check(&self, phase: Phase) -> Option<Fault> {
+        match phase {
+            Phase::Grinding if self.grinder_jam => Some(Fault::GrinderJam),
+            Phase::Frothing if self.frother_failure => Some(Fault::FrotherFailure),
+            Phase::Brewing if rand::random::<f64>() < self.failure_probability => {
+                Some(Fault::BrewFailure)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Request payload for PUT /admin/faults, missing fields keep their current value
+#[derive(Deserialize, ToSchema)]
+pub struct FaultUpdate {
+    #[serde(default)]
+    pub grinder_jam: Option<bool>,
+    #[serde(default)]
+    pub frother_failure: Option<bool>,
+    #[serde(default)]
+    pub failure_probability: Option<f64>,
+    #[serde(default)]
+    pub slow_brew_factor: Option<f64>,
+}
+
+/// GET /admin/faults endpoint returning the injected faults
+#[utoipa::path(
+    get,
+    path = "/admin/faults",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "Currently injected faults", body = FaultConfig, content_type = "application/json"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only admins may inject faults", body = ErrorResponse)
+    )
+)]
+pub async fn get_faults(
+    principal: Principal,                                 // Authenticated caller
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+) -> Result<Json<FaultConfig>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Admin])?;
+    Ok(Json(state.lock().unwrap().faults.clone()))
+}
+
+/// PUT /admin/faults endpoint changing the injected faults
+#[utoipa::path(
+    put,
+    path = "/admin/faults",
+    tag = "Admin",
+    request_body(content = FaultUpdate, content_type = "application/json"),
+    responses(
+        (status = 200, description = "Faults updated", body = FaultConfig, content_type = "application/json"),
+        (status = 400, description = "Invalid fault configuration", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only admins may inject faults", body = ErrorResponse)
+    )
+)]
+pub async fn put_faults(
+    principal: Principal,                                 // Authenticated caller
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+    Json(payload): Json<FaultUpdate>,
+) -> Result<Json<FaultConfig>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Admin])?;
+    let mut st = state.lock().unwrap();
+    let mut faults = st.faults.clone();
+    if let Some(v) = payload.grinder_jam {
+        faults.grinder_jam = v;
+    }
+    if let Some(v) = payload.frother_failure {
+        faults.frother_failure = v;
+    }
+    if let Some(v) = payload.failure_probability {
+        faults.failure_probability = v;
+    }
+    if let Some(v) = payload.slow_brew_factor {
+        faults.slow_brew_factor = v;
+    }
+    faults
+        .validate()
+        .map_err(|error| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error })))?;
+
+    tracing::warn!("Injected faults changed: {:?}", faults);
+    st.faults = faults.clone();
+    Ok(Json(faults))
+}
+
+/// DELETE /admin/faults endpoint removing all injected faults
+#[utoipa::path(
+    delete,
+    path = "/admin/faults",
+    tag = "Admin",
+    responses(
+        (status = 200, description = "All faults removed", body = FaultConfig, content_type = "application/json"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only admins may inject faults", body = ErrorResponse)
+    )
+)]
+pub async fn delete_faults(
+    principal: Principal,                                 // Authenticated caller
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+) -> Result<Json<FaultConfig>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Admin])?;
+    let mut st = state.lock().unwrap();
+    st.faults = FaultConfig::default();
+    tracing::info!("Injected faults cleared");
+    Ok(Json(st.faults.clone()))
+}
@@ -0,0 +1,32 @@
+use crate::status::StatusState;
+use axum::{Extension, Json, http::StatusCode};
+use probes::{Check, HealthResponse};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// Handler for GET /health/ready, up while the brewing units consume orders from RabbitMQ
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "Health",
+    responses(
+        (status = 200, description = "The machine takes orders", body = HealthResponse, content_type = "application/json"),
+        (status = 503, description = "A dependency is unavailable", body = HealthResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_ready(
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+) -> (StatusCode, Json<HealthResponse>) {
+    let connected = state
+        .lock()
+        .unwrap()
+        .broker
+        .as_ref()
+        .map(|status| status.connected());
+    let broker = match connected {
+        Some(true) => Check::up("Connected, consuming from order.placed"),
+        Some(false) => Check::down("Connection to RabbitMQ lost"),
+        None => Check::down("Consumers are not running"),
+    };
+    HealthResponse::from_checks(BTreeMap::from([("rabbitmq".to_string(), broker)]))
+}
@@ -0,0 +1,141 @@
+use crate::ErrorResponse;
+use crate::status::{OrderStatus, StatusState};
+use axum::{Extension, Json, extract::Query, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use utoipa::{IntoParams, ToSchema};
+
+/// Default number of brews returned per page
+const DEFAULT_LIMIT: usize = 20;
+/// Largest page size accepted by GET /status/history
+const MAX_LIMIT: usize = 100;
+
+/// A single finished brew
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BrewRecord {
+    pub order_id: String, // Order that was prepared
+    #[serde(rename = "type")]
+    pub r#type: String, // Beverage type: espresso, coffee, cappuccino
+    pub unit: usize,      // Brewing unit that prepared the order
+    pub started_at: DateTime<Utc>, // When the unit picked up the order
+    pub finished_at: DateTime<Utc>, // When the order was finished
+    pub duration_ms: i64, // Time between start and finish in milliseconds
+    pub outcome: OrderStatus, // Final status of the order
+}
+
+/// Bounded ring buffer of recent brews, optionally mirrored to a JSON lines file
+pub struct BrewHistory {
+    brews: VecDeque<BrewRecord>, // Oldest first
+    capacity: usize,             // Brews kept in memory
+    log_path: Option<PathBuf>,   // File every brew is appended to, if configured
+}
+
+impl BrewHistory {
+    /// Creates an empty history keeping at most `capacity` brews
+    pub fn new(capacity: usize, log_path: Option<PathBuf>) -> Self {
+        Self {
+            brews: VecDeque::with_capacity(capacity),
+            capacity,
+            log_path,
+        }
+    }
+
+    /// Adds a brew, dropping the oldest one once the buffer is full
+    pub fn record(&mut self, brew: BrewRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.brews.len() == self.capacity {
+            self.brews.pop_front();
+        }
+        self.brews.push_back(brew);
+    }
+
+    /// File brews are appended to, if configured
+    pub fn log_path(&self) -> Option<&Path> {
+        self.log_path.as_deref()
+    }
+
+    /// Returns a page of brews, newest first, together with the total number of brews kept
+    pub fn page(&self, offset: usize, limit: usize) -> (usize, Vec<BrewRecord>) {
+        let brews = self
+            .brews
+            .iter()
+            .rev()
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect();
+        (self.brews.len(), brews)
+    }
+}
+
+/// Appends a brew as a single JSON line to the persistent brew log
+pub async fn append_to_log(path: &Path, brew: &BrewRecord) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(brew)?;
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(&line).await?;
+    Ok(())
+}
+
+/// Query parameters for GET /status/history
+#[derive(Deserialize, IntoParams)]
+pub struct HistoryQuery {
+    /// Number of newest brews to skip
+    pub offset: Option<usize>,
+    /// Maximum number of brews to return (1-100, default 20)
+    pub limit: Option<usize>,
+}
+
+/// Defines the JSON structure returned by GET /status/history endpoint
+#[derive(Serialize, ToSchema)]
+pub struct HistoryResponse {
+    pub total: usize,           // Brews kept in the history
+    pub offset: usize,          // Brews skipped
+    pub limit: usize,           // Page size
+    pub brews: Vec<BrewRecord>, // Brews on this page, newest first
+}
+
+/// GET /status/history endpoint returning recent brews
+#[utoipa::path(
+    get,
+    path = "/status/history",
+    tag = "Status",
+    params(HistoryQuery),
+    responses(
+        (status = 200, description = "Recent brews, newest first", body = HistoryResponse, content_type = "application/json"),
+        (status = 400, description = "Invalid pagination", body = ErrorResponse)
+    )
+)]
+pub async fn get_history(
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<HistoryResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("limit must be between 1 and {}", MAX_LIMIT),
+            }),
+        ));
+    }
+
+    let (total, brews) = state.lock().unwrap().history.page(offset, limit);
+    Ok(Json(HistoryResponse {
+        total,
+        offset,
+        limit,
+        brews,
+    }))
+}
@@ -1,8 +1,13 @@
+use auth::API_KEY_HEADER;
 use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderValue};
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::time::Duration;
 use thiserror::Error;
 
+/// How long a request to the Inventory Service may take before it is given up
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Represents the current stock of beans and milk from the Inventory Service
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Stock {
@@ -19,41 +24,96 @@ pub enum InventoryError {
     Status(reqwest::StatusCode), // non-success HTTP status codes
 }
 
-/// Helper function to determine the base URL for the Inventory Service from environment variables
-fn base_url() -> String {
-    env::var("INVENTORY_SERVICE_URL").unwrap_or_else(|_| "http://localhost:8081".to_string())
+impl InventoryError {
+    /// Might the same request succeed when it is retried later?
+    pub fn is_transient(&self) -> bool {
+        match self {
+            InventoryError::Request(_) => true,
+            InventoryError::Status(status) => status.is_server_error(),
+        }
+    }
+}
+
+/// Client of the Inventory Service, addressing this machine's own stock
+#[derive(Debug)]
+pub struct InventoryClient {
+    http: Client,                 // Shared HTTP connection pool
+    base_url: String,             // Base URL of the Inventory Service
+    machine_id: String,           // Id of this machine in the Inventory Service
+    api_key: Option<HeaderValue>, // Sent in the X-API-Key header if set
 }
 
-/// Fetches the current stock levels from the Inventory Service via GET /fill
-pub async fn get_stock() -> Stock {
-    let url = format!("{}/fill", base_url());
-    let client = Client::new();
-    let resp = client
-        .get(&url)
-        .send()
-        .await
-        .expect("Failed to call inventory GET /fill");
-
-    if !resp.status().is_success() {
-        panic!("Inventory Service returned error status: {}", resp.status());
+impl InventoryClient {
+    /// Creates a client for the given Inventory Service and machine, authenticating with the
+    /// API key if one is given
+    pub fn new(
+        base_url: &str,
+        machine_id: &str,
+        api_key: Option<&str>,
+    ) -> Result<Self, InventoryError> {
+        // The configuration has already checked that the key is a valid header value
+        let api_key = api_key
+            .and_then(|key| HeaderValue::from_str(key).ok())
+            .map(|mut key| {
+                key.set_sensitive(true);
+                key
+            });
+        Ok(Self {
+            http: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            machine_id: machine_id.to_string(),
+            api_key,
+        })
     }
 
-    resp.json::<Stock>()
-        .await
-        .expect("Failed to deserialize inventory response")
-}
+    /// Trace context and API key headers of a request
+    fn headers(&self) -> HeaderMap {
+        let mut headers = telemetry::http_headers();
+        if let Some(key) = &self.api_key {
+            headers.insert(API_KEY_HEADER, key.clone());
+        }
+        headers
+    }
+
+    /// URL of this machine's stock
+    fn fill_url(&self) -> String {
+        format!("{}/machines/{}/fill", self.base_url, self.machine_id)
+    }
+
+    /// Fetches this machine's current stock levels from the Inventory Service via GET /machines/{id}/fill
+    #[tracing::instrument(name = "inventory get_stock", skip(self), fields(otel.kind = "client"))]
+    pub async fn get_stock(&self) -> Result<Stock, InventoryError> {
+        let resp = self
+            .http
+            .get(self.fill_url())
+            .headers(self.headers())
+            .send()
+            .await?;
+
+        if !resp.status().is_success() {
+            return Err(InventoryError::Status(resp.status()));
+        }
+
+        Ok(resp.json::<Stock>().await?)
+    }
 
-/// Deducts the specified amounts of beans and milk from the Inventory Service via DELETE /fill
-pub async fn deduct_stock(beans: u32, milk: u32) -> Result<(), InventoryError> {
-    let url = format!("{}/fill", base_url());
-    let client = Client::new();
-    let payload = Stock { beans, milk };
+    /// Deducts the specified amounts of beans and milk from this machine's stock via DELETE /machines/{id}/fill
+    #[tracing::instrument(name = "inventory deduct_stock", skip(self), fields(otel.kind = "client"))]
+    pub async fn deduct_stock(&self, beans: u32, milk: u32) -> Result<(), InventoryError> {
+        let payload = Stock { beans, milk };
 
-    let resp = client.delete(&url).json(&payload).send().await?;
+        let resp = self
+            .http
+            .delete(self.fill_url())
+            .headers(self.headers())
+            .json(&payload)
+            .send()
+            .await?;
 
-    if resp.status().is_success() {
-        Ok(())
-    } else {
-        Err(InventoryError::Status(resp.status()))
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            Err(InventoryError::Status(resp.status()))
+        }
     }
 }
@@ -0,0 +1,117 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// Number of transitions remembered per brewing unit
+const MAX_TRANSITIONS: usize = 20;
+
+/// Phases a brewing unit goes through
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Idle,         // Ready for a new order
+    Grinding,     // Grinding beans
+    Brewing,      // Extracting coffee
+    Frothing,     // Frothing milk
+    Dispensing,   // Pouring the drink into the cup
+    Error,        // Something went wrong while preparing an order
+    Cleaning,     // Running a cleaning cycle
+    OutOfService, // Taken out of service, no orders are accepted
+}
+
+impl Phase {
+    /// Returns true if a unit may move from this phase to the next one
+    pub fn can_transition_to(self, next: Phase) -> bool {
+        use Phase::*;
+        matches!(
+            (self, next),
+            // Regular brew cycle
+            (Idle, Grinding)
+                | (Grinding, Brewing)
+                | (Brewing, Frothing)
+                | (Brewing, Dispensing)
+                | (Frothing, Dispensing)
+                | (Dispensing, Idle)
+                // Any active phase can fail
+                | (Grinding | Brewing | Frothing | Dispensing | Cleaning, Error)
+                // Maintenance and recovery
+                | (Idle | Error, Cleaning)
+                | (Cleaning, Idle)
+                | (Error, Idle)
+                | (Idle | Error, OutOfService)
+                | (OutOfService, Idle)
+        )
+    }
+}
+
+/// A recorded change from one phase to another
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Transition {
+    pub from: Phase,
+    pub to: Phase,
+    pub at: DateTime<Utc>,
+}
+
+/// Error returned when a transition is not allowed from the current phase
+#[derive(Debug, Error)]
+#[error("Invalid transition from {from:?} to {to:?}")]
+pub struct InvalidTransition {
+    pub from: Phase,
+    pub to: Phase,
+}
+
+/// State machine of a single brewing unit
+pub struct StateMachine {
+    phase: Phase,                      // Current phase
+    since: DateTime<Utc>,              // When the current phase was entered
+    transitions: VecDeque<Transition>, // Most recent transitions, oldest first
+}
+
+impl StateMachine {
+    /// Creates an idle state machine
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            phase: Phase::Idle,
+            since: now,
+            transitions: VecDeque::new(),
+        }
+    }
+
+    /// Current phase
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// When the current phase was entered
+    pub fn since(&self) -> DateTime<Utc> {
+        self.since
+    }
+
+    /// Most recent transitions, oldest first
+    pub fn transitions(&self) -> Vec<Transition> {
+        self.transitions.iter().cloned().collect()
+    }
+
+    /// Moves to the next phase if the transition is allowed, returning the recorded transition
+    pub fn transition(
+        &mut self,
+        to: Phase,
+        at: DateTime<Utc>,
+    ) -> Result<Transition, InvalidTransition> {
+        let from = self.phase;
+        if !from.can_transition_to(to) {
+            return Err(InvalidTransition { from, to });
+        }
+
+        if self.transitions.len() == MAX_TRANSITIONS {
+            self.transitions.pop_front();
+        }
+        let transition = Transition { from, to, at };
+        self.transitions.push_back(transition.clone());
+        self.phase = to;
+        self.since = at;
+        Ok(transition)
+    }
+}
@@ -1,53 +1,196 @@
+mod clock;
+mod config;
+mod faults;
+mod health;
+mod history;
 mod inventory;
+mod machine;
+mod maintenance;
+mod metrics;
 mod rabbitmq;
+mod recipe;
 mod status;
+mod stream;
+mod units;
 
-use axum::{Extension, Router};
+use axum::{Extension, Router, routing::get};
+use clap::Parser;
+use clock::ScaledClock;
+use config::Config;
+use history::BrewHistory;
+use maintenance::Maintenance;
+use recipe::Recipes;
+use serde::Serialize;
 use status::StatusState;
 use std::net::Ipv4Addr;
+use std::time::Duration;
 use std::{
     net::SocketAddr,
     sync::{Arc, Mutex},
 };
 use tokio::net::TcpListener;
 use tokio::spawn;
-use tracing::info;
-use utoipa::OpenApi;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use utoipa::{OpenApi, ToSchema};
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_swagger_ui::SwaggerUi;
 
+/// Error payload returned by the REST endpoints
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: String, // Human-readable error message
+}
+
+impl From<auth::AuthError> for ErrorResponse {
+    fn from(err: auth::AuthError) -> Self {
+        ErrorResponse {
+            error: err.to_string(),
+        }
+    }
+}
+
 // Define OpenAPI documentation for the service
 #[derive(OpenApi)]
 #[openapi(
-    paths(status::get_status),
-    components(schemas(status::StatusResponse)),
+    paths(
+        status::get_status,
+        history::get_history,
+        stream::get_status_stream,
+        stream::get_status_ws,
+        maintenance::get_maintenance,
+        maintenance::post_clean,
+        maintenance::post_descale,
+        faults::get_faults,
+        faults::put_faults,
+        faults::delete_faults,
+        units::post_out_of_service,
+        units::post_restore,
+        probes::get_live,
+        health::get_ready
+    ),
+    components(schemas(
+        status::StatusResponse,
+        status::UnitStatus,
+        status::OrderStatus,
+        machine::Phase,
+        machine::Transition,
+        history::BrewRecord,
+        history::HistoryResponse,
+        stream::StatusEvent,
+        maintenance::MaintenanceKind,
+        maintenance::MaintenanceStatus,
+        faults::FaultConfig,
+        faults::FaultUpdate,
+        probes::HealthResponse,
+        probes::HealthStatus,
+        probes::Check,
+        ErrorResponse
+    )),
     tags(
-        (name = "Orders", description = "Order APIs")
+        (name = "Orders", description = "Order APIs"),
+        (name = "Maintenance", description = "Cleaning and descaling"),
+        (name = "Admin", description = "Fault injection and taking units out of service"),
+        (name = "Health", description = "Liveness and readiness probes")
     )
 )]
 struct ApiDoc;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing subscriber for structured logging
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    // Load and validate the configuration from file, environment and command line
+    let cli = config::Cli::parse();
+    let config = Config::load(&cli)?;
+    if cli.print_config {
+        print!("{}", toml::to_string_pretty(&config.redacted())?);
+        return Ok(());
+    }
+    let config = Arc::new(config);
+
+    // Initialize structured logging and optional OTLP span export
+    let tracer_provider = telemetry::init("machine-service")?;
+
+    // In-memory brew history and optional file every brew is appended to
+    let history = BrewHistory::new(config.history.size, config.history.log_path.clone());
+
+    // Maintenance schedule: cleaning every N shots, descaling every M litres of water
+    let schedule = &config.maintenance;
+    let maintenance = Maintenance::new(
+        schedule.clean_every_shots,
+        schedule
+            .descale_every_ml()
+            .ok_or("maintenance.descale_every_litres is too large")?,
+        Duration::from_secs(schedule.cleaning_duration_secs),
+        Duration::from_secs(schedule.descaling_duration_secs),
+    );
 
-    // Initialize shared machine status state wrapped in a thread-safe mutex
-    let shared_state = Arc::new(Mutex::new(StatusState::new()));
+    // Simulation speed: 1 = real time, 10 = ten times faster, 0 = instant
+    let clock = Arc::new(ScaledClock::new(config.time_scale)?);
 
-    // Start the RabbitMQ consumer in the background, passing cloned state
+    // Ingredients and phase durations of every beverage
+    let recipes = Recipes::new(&config.brew_ms)?;
+
+    // Prometheus metrics, exposed on /metrics
+    let metrics = Arc::new(metrics::Metrics::new()?);
+
+    // Initialize shared machine status state wrapped in a thread-safe mutex,
+    // injected faults start as configured and can be changed via /admin/faults
+    let shared_state = Arc::new(Mutex::new(StatusState::new(
+        config.units,
+        history,
+        maintenance,
+        config.faults.clone(),
+        clock,
+        recipes,
+        metrics.clone(),
+    )));
+
+    // Verifies bearer tokens and API keys, every caller is an admin while auth is disabled
+    let authenticator = Arc::new(auth::Authenticator::new(&config.auth)?);
+    if !config.auth.enabled {
+        warn!("Authentication is disabled, every caller may inject faults and run maintenance");
+    }
+
+    // Cancelled on SIGINT or SIGTERM, stops the HTTP server, the streams and the consumers
+    let shutdown = CancellationToken::new();
+    spawn(signals::cancel_on_shutdown(shutdown.clone()));
+
+    // Start the RabbitMQ consumers in the background, passing cloned state
     let consumer_state = shared_state.clone();
-    spawn(async move {
-        rabbitmq::Consumer::run(consumer_state)
-            .await
-            .expect("Consumer encountered an unrecoverable error");
+    let consumer_shutdown = shutdown.clone();
+    let consumer_shutdown_on_error = shutdown.clone();
+    let consumer_config = config.clone();
+    let consumer = spawn(async move {
+        let result =
+            rabbitmq::Consumer::run(consumer_state.clone(), &consumer_config, consumer_shutdown)
+                .await;
+        // The machine takes no more orders once the consumers have stopped
+        consumer_state.lock().unwrap().broker = None;
+        // A machine that cannot take orders shuts down instead of serving a stale status
+        if let Err(e) = result {
+            tracing::error!(error=%e, "Consumer encountered an unrecoverable error, shutting down");
+            consumer_shutdown_on_error.cancel();
+        }
     });
 
     // Build the OpenAPI router and specification
     let (api_router, api_spec) = OpenApiRouter::with_openapi(ApiDoc::openapi())
         .routes(utoipa_axum::routes![status::get_status])
+        .routes(utoipa_axum::routes![history::get_history])
+        .routes(utoipa_axum::routes![stream::get_status_stream])
+        .routes(utoipa_axum::routes![stream::get_status_ws])
+        .routes(utoipa_axum::routes![maintenance::get_maintenance])
+        .routes(utoipa_axum::routes![maintenance::post_clean])
+        .routes(utoipa_axum::routes![maintenance::post_descale])
+        .routes(utoipa_axum::routes![
+            faults::get_faults,
+            faults::put_faults,
+            faults::delete_faults
+        ])
+        .routes(utoipa_axum::routes![units::post_out_of_service])
+        .routes(utoipa_axum::routes![units::post_restore])
+        .routes(utoipa_axum::routes![probes::get_live])
+        .routes(utoipa_axum::routes![health::get_ready])
         .split_for_parts();
 
     // Construct the main application router
@@ -56,23 +199,40 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", api_spec.clone()))
         // Mount API endpoints
         .merge(api_router)
+        // Expose Prometheus metrics
+        .route("/metrics", get(metrics::get_metrics))
+        // Trace every request, continuing the caller's trace
+        .layer(axum::middleware::from_fn(telemetry::trace_request))
         // Make shared state available to handlers via Axum extension
-        .layer(Extension(shared_state));
+        .layer(Extension(shared_state))
+        .layer(Extension(metrics))
+        // Identify callers for the role checks of the handlers
+        .layer(Extension(authenticator))
+        // Let long-lived streams end on shutdown
+        .layer(Extension(shutdown.clone()));
 
-    // Determine service port from environment or default to 8082
-    let port: u16 = std::env::var("SERVICE_PORT")
-        .unwrap_or_else(|_| "8082".into())
-        .parse()?;
-    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    // Listen on the configured port, 8082 by default
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.port));
 
     // Bind TCP listener
     let listener = TcpListener::bind(&addr).await.unwrap();
     info!("Listening on {}", addr);
 
-    // Start the Axum HTTP server
+    // Start the Axum HTTP server, it stops accepting requests once shutdown begins
     axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(shutdown.cancelled_owned())
         .await
         .unwrap();
 
+    // Let the brewing units finish or requeue their in-flight orders
+    if let Err(e) = consumer.await {
+        tracing::error!(error=%e, "Consumer stopped abnormally");
+    }
+    // Flush spans that have not been exported yet
+    if let Some(Err(e)) = tracer_provider.map(|provider| provider.shutdown()) {
+        tracing::error!(error=%e, "Flushing spans failed");
+    }
+    info!("Shutdown complete");
+
     Ok(())
 }
@@ -0,0 +1,307 @@
+use crate::ErrorResponse;
+use crate::machine::Phase;
+use crate::status::StatusState;
+use crate::stream::StatusEvent;
+use auth::{Principal, Role};
+use axum::{Extension, Json, http::StatusCode};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+/// Kind of maintenance cycle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceKind {
+    Cleaning,  // Due every CLEAN_EVERY_SHOTS shots
+    Descaling, // Due every DESCALE_EVERY_LITRES litres of water
+}
+
+impl fmt::Display for MaintenanceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MaintenanceKind::Cleaning => write!(f, "cleaning"),
+            MaintenanceKind::Descaling => write!(f, "descaling"),
+        }
+    }
+}
+
+/// Errors that can occur when starting a maintenance cycle
+#[derive(Debug, Error)]
+pub enum MaintenanceError {
+    #[error("A {0} cycle is already running")]
+    AlreadyRunning(MaintenanceKind),
+    #[error("Brewing units are still preparing orders")]
+    Busy,
+}
+
+impl From<MaintenanceError> for (StatusCode, Json<ErrorResponse>) {
+    fn from(err: MaintenanceError) -> Self {
+        (
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: err.to_string(),
+            }),
+        )
+    }
+}
+
+/// Usage counters and maintenance schedule of the machine
+pub struct Maintenance {
+    pub total_shots: u64,              // Shots pulled since the service started
+    pub total_water_ml: u64,           // Water used since the service started
+    pub shots_since_cleaning: u64,     // Shots pulled since the last cleaning
+    pub water_ml_since_descaling: u64, // Water used since the last descaling
+    pub clean_every_shots: u64,        // Cleaning is due after this many shots
+    pub descale_every_ml: u64,         // Descaling is due after this much water
+    pub cleaning_duration: Duration,   // Length of a cleaning cycle
+    pub descaling_duration: Duration,  // Length of a descaling cycle
+    pub last_cleaned_at: Option<DateTime<Utc>>,
+    pub last_descaled_at: Option<DateTime<Utc>>,
+    pub running: Option<MaintenanceKind>, // Cycle currently running, if any
+}
+
+impl Maintenance {
+    /// Creates a freshly maintained machine with the given schedule
+    pub fn new(
+        clean_every_shots: u64,
+        descale_every_ml: u64,
+        cleaning_duration: Duration,
+        descaling_duration: Duration,
+    ) -> Self {
+        Self {
+            total_shots: 0,
+            total_water_ml: 0,
+            shots_since_cleaning: 0,
+            water_ml_since_descaling: 0,
+            clean_every_shots,
+            descale_every_ml,
+            cleaning_duration,
+            descaling_duration,
+            last_cleaned_at: None,
+            last_descaled_at: None,
+            running: None,
+        }
+    }
+
+    /// Maintenance cycles that are currently due
+    pub fn due(&self) -> Vec<MaintenanceKind> {
+        let mut due = Vec::new();
+        if self.shots_since_cleaning >= self.clean_every_shots {
+            due.push(MaintenanceKind::Cleaning);
+        }
+        if self.water_ml_since_descaling >= self.descale_every_ml {
+            due.push(MaintenanceKind::Descaling);
+        }
+        due
+    }
+
+    /// Is brewing blocked by a due or running maintenance cycle?
+    pub fn blocks_brewing(&self) -> bool {
+        self.running.is_some() || !self.due().is_empty()
+    }
+
+    /// Counts a brew, returning the maintenance cycles that became due because of it
+    pub fn record(&mut self, shots: u64, water_ml: u64) -> Vec<MaintenanceKind> {
+        let before = self.due();
+        self.total_shots += shots;
+        self.total_water_ml += water_ml;
+        self.shots_since_cleaning += shots;
+        self.water_ml_since_descaling += water_ml;
+        self.due()
+            .into_iter()
+            .filter(|kind| !before.contains(kind))
+            .collect()
+    }
+
+    /// Length of a maintenance cycle
+    pub fn duration(&self, kind: MaintenanceKind) -> Duration {
+        match kind {
+            MaintenanceKind::Cleaning => self.cleaning_duration,
+            MaintenanceKind::Descaling => self.descaling_duration,
+        }
+    }
+
+    /// Resets the counter of a finished maintenance cycle
+    pub fn complete(&mut self, kind: MaintenanceKind, now: DateTime<Utc>) {
+        match kind {
+            MaintenanceKind::Cleaning => {
+                self.shots_since_cleaning = 0;
+                self.last_cleaned_at = Some(now);
+            }
+            MaintenanceKind::Descaling => {
+                self.water_ml_since_descaling = 0;
+                self.last_descaled_at = Some(now);
+            }
+        }
+        self.running = None;
+    }
+
+    /// Summarizes counters and schedule for the REST API
+    pub fn status(&self) -> MaintenanceStatus {
+        MaintenanceStatus {
+            due: self.due(),
+            running: self.running,
+            total_shots: self.total_shots,
+            total_water_ml: self.total_water_ml,
+            shots_since_cleaning: self.shots_since_cleaning,
+            water_ml_since_descaling: self.water_ml_since_descaling,
+            clean_every_shots: self.clean_every_shots,
+            descale_every_ml: self.descale_every_ml,
+            last_cleaned_at: self.last_cleaned_at,
+            last_descaled_at: self.last_descaled_at,
+        }
+    }
+}
+
+/// Defines the JSON structure returned by the maintenance endpoints
+#[derive(Serialize, ToSchema)]
+pub struct MaintenanceStatus {
+    pub due: Vec<MaintenanceKind>, // Cycles that block brewing until they are run
+    pub running: Option<MaintenanceKind>, // Cycle currently running, if any
+    pub total_shots: u64,
+    pub total_water_ml: u64,
+    pub shots_since_cleaning: u64,
+    pub water_ml_since_descaling: u64,
+    pub clean_every_shots: u64,
+    pub descale_every_ml: u64,
+    pub last_cleaned_at: Option<DateTime<Utc>>,
+    pub last_descaled_at: Option<DateTime<Utc>>,
+}
+
+impl StatusState {
+    /// Counts a finished brew and announces maintenance cycles that became due
+    pub fn record_usage(&mut self, shots: u64, water_ml: u64) {
+        for kind in self.maintenance.record(shots, water_ml) {
+            tracing::warn!(
+                "Maintenance due: {}, brewing is blocked until it has run",
+                kind
+            );
+            // Sending only fails when nobody is subscribed
+            let _ = self.events.send(StatusEvent::MaintenanceDue {
+                kind,
+                at: self.clock.now(),
+            });
+        }
+    }
+
+    /// Starts a maintenance cycle on all brewing units
+    pub fn begin_maintenance(&mut self, kind: MaintenanceKind) -> Result<(), MaintenanceError> {
+        if let Some(running) = self.maintenance.running {
+            return Err(MaintenanceError::AlreadyRunning(running));
+        }
+        if self.units.iter().any(|u| u.current_order_id.is_some()) {
+            return Err(MaintenanceError::Busy);
+        }
+
+        // Failed units recover through the cycle, units out of service sit it out
+        self.maintenance.running = Some(kind);
+        for unit in 1..=self.units.len() {
+            if self.units[unit - 1].machine.phase() == Phase::OutOfService {
+                continue;
+            }
+            if let Err(e) = self.advance(unit, Phase::Cleaning) {
+                tracing::error!(error=%e, "Brewing unit {} rejected phase change", unit);
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes a maintenance cycle and returns the cleaned brewing units to idle
+    pub fn end_maintenance(&mut self, kind: MaintenanceKind) {
+        for unit in 1..=self.units.len() {
+            if self.units[unit - 1].machine.phase() != Phase::Cleaning {
+                continue;
+            }
+            if let Err(e) = self.advance(unit, Phase::Idle) {
+                tracing::error!(error=%e, "Brewing unit {} rejected phase change", unit);
+            }
+        }
+        let now = self.clock.now();
+        self.maintenance.complete(kind, now);
+        tracing::info!("Maintenance finished: {}", kind);
+    }
+}
+
+/// Starts a maintenance cycle and finishes it in the background
+fn run_cycle(
+    state: Arc<Mutex<StatusState>>,
+    kind: MaintenanceKind,
+) -> Result<MaintenanceStatus, MaintenanceError> {
+    let (duration, status, clock) = {
+        let mut st = state.lock().unwrap();
+        st.begin_maintenance(kind)?;
+        (
+            st.maintenance.duration(kind),
+            st.maintenance.status(),
+            st.clock.clone(),
+        )
+    };
+    tracing::info!("Maintenance started: {} for {:?}", kind, duration);
+
+    tokio::spawn(async move {
+        clock.sleep(duration).await;
+        state.lock().unwrap().end_maintenance(kind);
+    });
+    Ok(status)
+}
+
+/// GET /maintenance endpoint returning usage counters and due maintenance
+#[utoipa::path(
+    get,
+    path = "/maintenance",
+    tag = "Maintenance",
+    responses(
+        (status = 200, description = "Usage counters and maintenance schedule", body = MaintenanceStatus, content_type = "application/json")
+    )
+)]
+pub async fn get_maintenance(
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+) -> Json<MaintenanceStatus> {
+    Json(state.lock().unwrap().maintenance.status())
+}
+
+/// POST /maintenance/clean endpoint running a simulated cleaning cycle
+#[utoipa::path(
+    post,
+    path = "/maintenance/clean",
+    tag = "Maintenance",
+    responses(
+        (status = 202, description = "Cleaning cycle started", body = MaintenanceStatus, content_type = "application/json"),
+        (status = 409, description = "Units are busy or another cycle is running", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas may run maintenance", body = ErrorResponse)
+    )
+)]
+pub async fn post_clean(
+    principal: Principal,                                 // Authenticated caller
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+) -> Result<(StatusCode, Json<MaintenanceStatus>), (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Barista])?;
+    let status = run_cycle(state, MaintenanceKind::Cleaning)?;
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+/// POST /maintenance/descale endpoint running a simulated descaling cycle
+#[utoipa::path(
+    post,
+    path = "/maintenance/descale",
+    tag = "Maintenance",
+    responses(
+        (status = 202, description = "Descaling cycle started", body = MaintenanceStatus, content_type = "application/json"),
+        (status = 409, description = "Units are busy or another cycle is running", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only baristas may run maintenance", body = ErrorResponse)
+    )
+)]
+pub async fn post_descale(
+    principal: Principal,                                 // Authenticated caller
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+) -> Result<(StatusCode, Json<MaintenanceStatus>), (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Barista])?;
+    let status = run_cycle(state, MaintenanceKind::Descaling)?;
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
@@ -0,0 +1,74 @@
+use axum::{extract::Extension, http::header, response::IntoResponse};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::Arc;
+
+/// Prometheus metrics of the machine service
+pub struct Metrics {
+    registry: Registry,              // Registry all metrics below belong to
+    pub brews: IntCounterVec,        // Finished brews by beverage type and outcome
+    pub brew_duration: HistogramVec, // Time to prepare an order, by beverage type
+    pub queue_wait: Histogram,       // Time orders waited before a unit took them
+    pub queue_depth: IntGauge,       // Orders waiting in 'order.placed'
+}
+
+impl Metrics {
+    /// Creates and registers all metrics
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let brews = IntCounterVec::new(
+            Opts::new("brews_total", "Finished brews"),
+            &["type", "outcome"],
+        )?;
+        let brew_duration = HistogramVec::new(
+            HistogramOpts::new("brew_duration_seconds", "Time to prepare an order")
+                .buckets(vec![0.5, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 20.0, 30.0, 60.0]),
+            &["type"],
+        )?;
+        let queue_wait = Histogram::with_opts(
+            HistogramOpts::new(
+                "order_queue_wait_seconds",
+                "Time between placing an order and a brewing unit picking it up",
+            )
+            .buckets(vec![0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0]),
+        )?;
+        let queue_depth = IntGauge::new(
+            "order_queue_depth",
+            "Orders waiting in the order.placed queue",
+        )?;
+        registry.register(Box::new(brews.clone()))?;
+        registry.register(Box::new(brew_duration.clone()))?;
+        registry.register(Box::new(queue_wait.clone()))?;
+        registry.register(Box::new(queue_depth.clone()))?;
+
+        Ok(Self {
+            registry,
+            brews,
+            brew_duration,
+            queue_wait,
+            queue_depth,
+        })
+    }
+
+    /// Encodes all metrics in the Prometheus text format
+    fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!(error=%e, "Failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Handler for GET /metrics, scraped by Prometheus
+pub async fn get_metrics(Extension(metrics): Extension<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            TextEncoder::new().format_type().to_string(),
+        )],
+        metrics.render(),
+    )
+}
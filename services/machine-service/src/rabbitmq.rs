@@ -1,16 +1,95 @@
-use crate::inventory;
-use crate::status::StatusState;
+use crate::config::Config;
+use crate::faults::Fault;
+use crate::history;
+use crate::inventory::{InventoryClient, InventoryError};
+use crate::machine::Phase;
+use crate::metrics::Metrics;
+use crate::status::{OrderStatus, StatusState};
+use crate::stream::StatusEvent;
+use anyhow::Context;
 use chrono::Utc;
 use futures_util::StreamExt;
 use lapin::{
-    Connection, ConnectionProperties,
-    options::{BasicAckOptions, BasicConsumeOptions, QueueDeclareOptions},
-    types::FieldTable,
+    BasicProperties, Channel, Connection, ConnectionProperties,
+    options::{
+        BasicAckOptions, BasicCancelOptions, BasicConsumeOptions, BasicNackOptions,
+        BasicPublishOptions, BasicQosOptions, QueueDeclareOptions,
+    },
+    types::{AMQPValue, FieldTable},
 };
 use serde::Deserialize;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
-use std::thread::sleep;
 use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// How often a unit checks whether maintenance still blocks brewing
+const MAINTENANCE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the number of orders waiting in 'order.placed' is sampled
+const QUEUE_DEPTH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a unit in error or out of service checks whether it can take orders again
+const RESTORE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a unit waits before requeueing an order that failed for a transient reason
+const REQUEUE_DELAY: Duration = Duration::from_secs(1);
+
+/// Queue orders are dead-lettered to once they can never be served
+const FAILED_QUEUE: &str = "order.failed";
+
+/// Reasons an order could not be prepared
+#[derive(Debug, Error)]
+pub enum PrepareError {
+    #[error("Unknown beverage type: {0}")]
+    UnknownType(String),
+    #[error("Insufficient ingredients")]
+    InsufficientIngredients,
+    #[error("Inventory Service unavailable: {0}")]
+    Inventory(#[from] InventoryError),
+    #[error("Deduction of the ingredients failed, they may have been deducted: {0}")]
+    Deduction(InventoryError),
+    #[error(transparent)]
+    Fault(#[from] Fault),
+}
+
+impl PrepareError {
+    /// Might the order be served when it is redelivered later?
+    fn is_transient(&self) -> bool {
+        match self {
+            PrepareError::Inventory(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+/// Outcome of processing an order, deciding how its delivery is settled
+#[derive(Debug)]
+pub enum OrderOutcome {
+    Done,                 // Served, acknowledged
+    Failed(PrepareError), // Can never be served, dead-lettered to 'order.failed'
+    Retry(PrepareError),  // Failed for a transient reason, requeued
+    Unavailable(Phase),   // The unit is in error or out of service, handed back to the other units
+    Interrupted,          // Not finished before shutdown, requeued for another machine
+}
+
+/// Arguments of the 'order.placed' queue; rejected orders are routed to 'order.failed'
+fn order_queue_args() -> FieldTable {
+    let mut args = FieldTable::default();
+    args.insert(
+        "x-dead-letter-exchange".into(),
+        AMQPValue::LongString("".into()),
+    );
+    args.insert(
+        "x-dead-letter-routing-key".into(),
+        AMQPValue::LongString(FAILED_QUEUE.into()),
+    );
+    args
+}
 
 /// Consumer handles incoming order messages from RabbitMQ and processes them
 pub struct Consumer;
@@ -24,36 +103,117 @@ pub struct OrderMessage {
 }
 
 impl Consumer {
-    /// Starts the RabbitMQ consumer loop using the provided shared status state
-    pub async fn run(state: Arc<Mutex<StatusState>>) -> anyhow::Result<()> {
-        // Load RabbitMQ connection settings from environment or use defaults
-        let host = std::env::var("RABBITMQ_HOST").unwrap_or_else(|_| "localhost".into());
-        let port: u16 = std::env::var("RABBITMQ_PORT")
-            .unwrap_or_else(|_| "5672".into())
-            .parse()?;
-        let user = std::env::var("RABBITMQ_USER").unwrap_or_else(|_| "user".into());
-        let pass = std::env::var("RABBITMQ_PASS").unwrap_or_else(|_| "pass".into());
-
-        // Build AMQP URI and attempt to connect with retry logic
-        let addr = format!("amqp://{}:{}@{}:{}/%2f", user, pass, host, port);
+    /// Starts one RabbitMQ consumer per brewing unit using the provided shared status state.
+    /// Once `shutdown` is cancelled, in-flight orders get the configured grace period to finish
+    /// before they are requeued, unless their ingredients are already being deducted.
+    pub async fn run(
+        state: Arc<Mutex<StatusState>>,
+        config: &Config,
+        shutdown: CancellationToken,
+    ) -> anyhow::Result<()> {
+        let grace = Duration::from_secs(config.shutdown_grace_secs);
+        let error_reset = Duration::from_secs(config.error_reset_secs);
+        let inventory = Arc::new(InventoryClient::new(
+            &config.inventory_url,
+            &config.machine_id,
+            config.inventory_api_key.as_deref(),
+        )?);
+
+        // Attempt to connect with retry logic
+        let addr = config.amqp.url();
         let conn = loop {
             let retry_delay = Duration::from_secs(1);
             match Connection::connect(&addr, ConnectionProperties::default()).await {
                 Ok(c) => break c,
                 Err(err) => {
                     tracing::error!(error=%err, "Failed to initialize RabbitMQ consumer. Retrying in {:?}...", retry_delay);
-                    sleep(retry_delay);
+                    tokio::select! {
+                        _ = shutdown.cancelled() => return Ok(()),
+                        _ = tokio::time::sleep(retry_delay) => {}
+                    }
                 }
             }
         };
 
-        // Create a channel on the established connection
+        // Report the connection to the readiness check
+        state.lock().unwrap().broker = Some(conn.status().clone());
+
+        // Each brewing unit consumes on its own channel, so the QoS prefetch applies per unit
+        let mut workers = Vec::with_capacity(config.units);
+        for unit in 1..=config.units {
+            let channel = conn
+                .create_channel()
+                .await
+                .context("Failed to create RabbitMQ channel")?;
+            channel
+                .basic_qos(config.prefetch, BasicQosOptions::default())
+                .await?;
+            workers.push(tokio::spawn(Self::consume(
+                unit,
+                channel,
+                state.clone(),
+                inventory.clone(),
+                shutdown.clone(),
+                grace,
+                error_reset,
+            )));
+        }
+
+        // Sample the queue depth for the metrics
+        let channel = conn
+            .create_channel()
+            .await
+            .context("Failed to create RabbitMQ channel")?;
+        let metrics = state.lock().unwrap().metrics.clone();
+        workers.push(tokio::spawn(Self::sample_queue_depth(
+            channel,
+            metrics,
+            shutdown.clone(),
+        )));
+
+        // Publish order progress and maintenance events for other services,
+        // until the units are done so their last updates still go out
         let channel = conn
             .create_channel()
             .await
-            .expect("Failed to create RabbitMQ channel");
+            .context("Failed to create RabbitMQ channel")?;
+        let events = state.lock().unwrap().events.subscribe();
+        let units_done = CancellationToken::new();
+        let publisher = tokio::spawn(Self::publish_events(channel, events, units_done.clone()));
+
+        // Wait for all units; an error in any of them is unrecoverable
+        for worker in workers {
+            worker.await??;
+        }
+        units_done.cancel();
+        publisher.await??;
+
+        conn.close(200, "Shutting down").await?;
+        tracing::info!("RabbitMQ consumer stopped");
+        Ok(())
+    }
 
-        // Declare the 'order.placed' queue idempotently
+    /// Consumer loop of a single brewing unit
+    async fn consume(
+        unit: usize,
+        channel: Channel,
+        state: Arc<Mutex<StatusState>>,
+        inventory: Arc<InventoryClient>,
+        shutdown: CancellationToken,
+        grace: Duration,
+        error_reset: Duration,
+    ) -> anyhow::Result<()> {
+        // Declare the 'order.failed' dead-letter queue and the 'order.placed' queue idempotently
+        channel
+            .queue_declare(
+                FAILED_QUEUE,
+                QueueDeclareOptions {
+                    durable: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
         let queue = channel
             .queue_declare(
                 "order.placed",
@@ -61,78 +221,486 @@ impl Consumer {
                     durable: true,
                     ..Default::default()
                 },
-                FieldTable::default(),
+                order_queue_args(),
             )
             .await?;
 
         // Start consuming messages from the queue
-        let mut consumer = channel
-            .basic_consume(
-                queue.name().as_str(),
-                "consumer",
-                BasicConsumeOptions::default(),
-                FieldTable::default(),
-            )
-            .await
-            .expect("Failed to start RabbitMQ consumer");
+        let consumer_tag = format!("unit-{}", unit);
+        let mut consumer = Self::subscribe(&channel, queue.name().as_str(), &consumer_tag).await?;
 
-        tracing::info!("Waiting for messages on queue '{}'", queue.name().as_str());
+        tracing::info!(
+            "Brewing unit {} waiting for messages on queue '{}'",
+            unit,
+            queue.name().as_str()
+        );
 
-        // Process each delivery as it arrives
-        while let Some(delivery) = consumer.next().await {
-            let delivery = delivery?;
+        // Process each delivery as it arrives, until shutdown
+        let mut subscribed = true;
+        loop {
+            let delivery = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                delivery = consumer.next() => match delivery {
+                    Some(delivery) => delivery?,
+                    None => break,
+                },
+            };
             let data = &delivery.data;
-            match serde_json::from_slice::<OrderMessage>(data) {
+            let requeue = match serde_json::from_slice::<OrderMessage>(data) {
                 Ok(order) => {
-                    // Process the valid order message
-                    Self::process_order(order, &state).await;
-                    // Acknowledge the message on success
-                    channel
-                        .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
-                        .await?;
+                    // Consumer lag: how long the order waited in the queue
+                    {
+                        let st = state.lock().unwrap();
+                        let waited = (st.clock.now() - order.timestamp).num_milliseconds();
+                        st.metrics.queue_wait.observe(waited.max(0) as f64 / 1000.0);
+                    }
+
+                    // Continue the trace of the order service, if the message carries one
+                    let span = tracing::info_span!(
+                        "process_order",
+                        order_id = %order.order_id,
+                        unit,
+                        otel.kind = "consumer",
+                    );
+                    span.set_parent(telemetry::amqp_context(
+                        delivery.properties.headers().as_ref(),
+                    ));
+
+                    // Process the valid order message; after shutdown it only has the grace period left
+                    let deadline = async {
+                        shutdown.cancelled().await;
+                        tokio::time::sleep(grace).await;
+                    };
+                    let deducting = AtomicBool::new(false);
+                    let processing = Self::process_order(
+                        unit,
+                        order,
+                        &state,
+                        &inventory,
+                        &shutdown,
+                        error_reset,
+                        &deducting,
+                    )
+                    .instrument(span);
+                    tokio::pin!(processing);
+                    let finished = tokio::select! {
+                        outcome = &mut processing => Some(outcome),
+                        _ = deadline => None,
+                    };
+                    let outcome = match finished {
+                        Some(outcome) => outcome,
+                        // A redelivered order would have its ingredients deducted a second time
+                        None if deducting.load(Ordering::SeqCst) => {
+                            tracing::warn!(
+                                "Unit {} finishes its order past the grace period, its ingredients are deducted",
+                                unit
+                            );
+                            processing.await
+                        }
+                        None => OrderOutcome::Interrupted,
+                    };
+                    match outcome {
+                        OrderOutcome::Done => None,
+                        OrderOutcome::Failed(e) => {
+                            tracing::error!(
+                                "Unit {} dead-letters its order to '{}': {}",
+                                unit,
+                                FAILED_QUEUE,
+                                e
+                            );
+                            Some(false)
+                        }
+                        OrderOutcome::Retry(e) => {
+                            tracing::warn!("Unit {} requeues its order for a retry: {}", unit, e);
+                            // Give the transient problem a moment to clear up before redelivery
+                            tokio::select! {
+                                _ = shutdown.cancelled() => {}
+                                _ = tokio::time::sleep(REQUEUE_DELAY) => {}
+                            }
+                            Some(true)
+                        }
+                        OrderOutcome::Unavailable(phase) => {
+                            tracing::warn!("Unit {} stops taking orders, it is {:?}", unit, phase);
+                            // Stop the deliveries before handing the orders back, so they go to
+                            // the units that are up or wait in the queue instead of bouncing back
+                            channel
+                                .basic_cancel(&consumer_tag, BasicCancelOptions::default())
+                                .await?;
+                            // Requeue this order and every one prefetched behind it
+                            channel
+                                .basic_nack(
+                                    0,
+                                    BasicNackOptions {
+                                        multiple: true,
+                                        requeue: true,
+                                    },
+                                )
+                                .await?;
+                            if !Self::wait_until_restored(unit, &state, &shutdown).await {
+                                subscribed = false;
+                                break;
+                            }
+                            tracing::info!("Unit {} takes orders again", unit);
+                            consumer =
+                                Self::subscribe(&channel, queue.name().as_str(), &consumer_tag)
+                                    .await?;
+                            continue;
+                        }
+                        OrderOutcome::Interrupted => {
+                            tracing::warn!("Unit {} requeues its order on shutdown", unit);
+                            Some(true)
+                        }
+                    }
                 }
                 Err(e) => {
-                    tracing::error!(error=%e, "Invalid message received, discarding");
-                    // Acknowledge to remove it from the queue (no requeue)
+                    tracing::error!(error=%e, "Invalid message received, dead-lettering");
+                    Some(false)
+                }
+            };
+
+            match requeue {
+                // Acknowledge the message only once the order is finished
+                None => {
                     channel
                         .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
-                        .await?;
+                        .await?
+                }
+                // Requeue for another attempt, or dead-letter to 'order.failed'
+                Some(requeue) => {
+                    channel
+                        .basic_nack(
+                            delivery.delivery_tag,
+                            BasicNackOptions {
+                                requeue,
+                                ..Default::default()
+                            },
+                        )
+                        .await?
                 }
             }
         }
 
+        // Stop receiving deliveries; prefetched but unacknowledged ones are requeued on close
+        if subscribed {
+            channel
+                .basic_cancel(&consumer_tag, BasicCancelOptions::default())
+                .await?;
+        }
+        channel.close(200, "Shutting down").await?;
+        tracing::info!("Brewing unit {} stopped", unit);
         Ok(())
     }
 
-    /// Handles the business logic for preparing an order
-    async fn process_order(order: OrderMessage, state: &Arc<Mutex<StatusState>>) {
+    /// Subscribes a brewing unit to the order queue
+    async fn subscribe(
+        channel: &Channel,
+        queue: &str,
+        consumer_tag: &str,
+    ) -> anyhow::Result<lapin::Consumer> {
+        channel
+            .basic_consume(
+                queue,
+                consumer_tag,
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .context("Failed to start RabbitMQ consumer")
+    }
+
+    /// Waits until a brewing unit is neither in error nor out of service; false if shutdown
+    /// comes first
+    async fn wait_until_restored(
+        unit: usize,
+        state: &Arc<Mutex<StatusState>>,
+        shutdown: &CancellationToken,
+    ) -> bool {
+        loop {
+            let phase = state.lock().unwrap().units[unit - 1].machine.phase();
+            if !matches!(phase, Phase::Error | Phase::OutOfService) {
+                return true;
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => return false,
+                _ = tokio::time::sleep(RESTORE_POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Periodically records the number of orders waiting in 'order.placed', until shutdown
+    async fn sample_queue_depth(
+        channel: Channel,
+        metrics: Arc<Metrics>,
+        shutdown: CancellationToken,
+    ) -> anyhow::Result<()> {
+        // The first sample is taken once the units have declared the queue
+        let start = tokio::time::Instant::now() + QUEUE_DEPTH_INTERVAL;
+        let mut ticker = tokio::time::interval_at(start, QUEUE_DEPTH_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = ticker.tick() => {}
+            }
+            // A passive declaration only inspects the queue
+            let declared = channel
+                .queue_declare(
+                    "order.placed",
+                    QueueDeclareOptions {
+                        passive: true,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await;
+            match declared {
+                Ok(queue) => metrics.queue_depth.set(queue.message_count().into()),
+                Err(e) => {
+                    // Brewing goes on without the metric
+                    tracing::error!(error=%e, "Failed to sample the queue depth, giving up");
+                    return Ok(());
+                }
+            }
+        }
+        channel.close(200, "Shutting down").await?;
+        Ok(())
+    }
+
+    /// Forwards status events to RabbitMQ: per-order progress to 'order.progress' and
+    /// due maintenance to 'maintenance.due'
+    async fn publish_events(
+        channel: Channel,
+        mut events: broadcast::Receiver<StatusEvent>,
+        stop: CancellationToken,
+    ) -> anyhow::Result<()> {
+        // Declare both queues idempotently
+        for queue in ["order.progress", "maintenance.due"] {
+            channel
+                .queue_declare(
+                    queue,
+                    QueueDeclareOptions {
+                        durable: true,
+                        ..Default::default()
+                    },
+                    FieldTable::default(),
+                )
+                .await?;
+        }
+
+        loop {
+            let received = tokio::select! {
+                _ = stop.cancelled() => break,
+                received = events.recv() => received,
+            };
+            match received {
+                Ok(event) => Self::publish_event(&channel, &event).await?,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!(
+                        "Progress publisher lagged behind, {} events skipped",
+                        missed
+                    );
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+
+        // Flush events that were raised before the units stopped
+        while let Ok(event) = events.try_recv() {
+            Self::publish_event(&channel, &event).await?;
+        }
+        channel.close(200, "Shutting down").await?;
+        Ok(())
+    }
+
+    /// Publishes a single status event to the queue it belongs to, if any
+    async fn publish_event(channel: &Channel, event: &StatusEvent) -> anyhow::Result<()> {
+        let (queue, payload) = match event {
+            StatusEvent::MaintenanceDue { .. } => ("maintenance.due", serde_json::to_vec(event)?),
+            _ => match event.progress() {
+                Some(progress) => ("order.progress", serde_json::to_vec(&progress)?),
+                None => return Ok(()),
+            },
+        };
+        channel
+            .basic_publish(
+                "",
+                queue,
+                BasicPublishOptions::default(),
+                &payload,
+                BasicProperties::default(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Occupies a brewing unit with an order and frees it again once the order is settled
+    async fn process_order(
+        unit: usize,
+        order: OrderMessage,
+        state: &Arc<Mutex<StatusState>>,
+        inventory: &InventoryClient,
+        shutdown: &CancellationToken,
+        error_reset: Duration,
+        deducting: &AtomicBool,
+    ) -> OrderOutcome {
+        // Hold the order while a maintenance cycle is due or running
+        let mut announced = false;
+        loop {
+            let phase = {
+                let mut st = state.lock().unwrap();
+                if st.try_start(unit, &order.order_id) {
+                    break;
+                }
+                st.units[unit - 1].machine.phase()
+            };
+            // A unit that is down hands the order back, another unit may serve it
+            if matches!(phase, Phase::Error | Phase::OutOfService) {
+                return OrderOutcome::Unavailable(phase);
+            }
+            if !announced {
+                tracing::warn!(
+                    "Order {} on unit {} waits for maintenance",
+                    order.order_id,
+                    unit
+                );
+                announced = true;
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => return OrderOutcome::Interrupted,
+                _ = tokio::time::sleep(MAINTENANCE_POLL_INTERVAL) => {}
+            }
+        }
+
+        let result = match Self::prepare_order(unit, &order, state, inventory, deducting).await {
+            // A transient failure before the deduction leaves no trace, the order is brewed again
+            // on redelivery
+            Err(e) if e.is_transient() => {
+                state.lock().unwrap().release(unit);
+                return OrderOutcome::Retry(e);
+            }
+            result => result,
+        };
+
+        // A unit that failed mid-brew stays in error until it is restored or resets itself
+        let phase = state.lock().unwrap().units[unit - 1].machine.phase();
+        if phase != Phase::Idle {
+            Self::fail(state, unit, error_reset);
+        }
+
+        // Update shared status state upon completion
+        let status = match result {
+            Ok(()) => OrderStatus::Done,
+            Err(_) => OrderStatus::Failed,
+        };
+        let (brew, log_path) = {
+            let mut st = state.lock().unwrap();
+            let brew = st.finish(unit, order.order_id.clone(), order.r#type.clone(), status);
+            (brew, st.history.log_path().map(|p| p.to_path_buf()))
+        };
+
+        // Mirror the brew to the persistent log, if configured
+        if let Some(path) = log_path {
+            let result = history::append_to_log(&path, &brew).await;
+            if let Err(e) = result {
+                tracing::error!(error=%e, "Failed to append brew to {}", path.display());
+            }
+        }
+
         tracing::info!(
-            "Processing order {} of type {}",
+            "Order {} finished on unit {} with status {}",
             order.order_id,
-            order.r#type
+            unit,
+            status
         );
+        match result {
+            Ok(()) => OrderOutcome::Done,
+            Err(e) => OrderOutcome::Failed(e),
+        }
+    }
 
-        // Determine ingredient requirements based on beverage type
-        let (beans, milk) = match order.r#type.as_str() {
-            "espresso" => (1, 0),
-            "coffee" => (2, 1),
-            "cappuccino" => (1, 2),
-            _ => {
-                tracing::error!("Unknown beverage type: {}", order.r#type);
+    /// Moves a brewing unit to the next phase, logging transitions that are not allowed
+    fn enter(state: &Arc<Mutex<StatusState>>, unit: usize, phase: Phase) {
+        if let Err(e) = state.lock().unwrap().advance(unit, phase) {
+            tracing::error!(error=%e, "Brewing unit {} rejected phase change", unit);
+        }
+    }
+
+    /// Moves a brewing unit to the error phase and, unless `reset` is zero, back to idle once
+    /// it has been in error for that long without being restored
+    fn fail(state: &Arc<Mutex<StatusState>>, unit: usize, reset: Duration) {
+        Self::enter(state, unit, Phase::Error);
+        if reset.is_zero() {
+            return;
+        }
+        // The reset is simulated time, so it runs as fast as the brews
+        let (failed_at, reset_elapsed) = {
+            let st = state.lock().unwrap();
+            (st.units[unit - 1].machine.since(), st.clock.sleep(reset))
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            reset_elapsed.await;
+            let mut st = state.lock().unwrap();
+            // Leave the unit alone if it was restored or has failed again since
+            let machine = &st.units[unit - 1].machine;
+            if machine.phase() != Phase::Error || machine.since() != failed_at {
                 return;
             }
+            tracing::info!(
+                "Brewing unit {} resets itself after {:?} in error",
+                unit,
+                reset
+            );
+            if let Err(e) = st.advance(unit, Phase::Idle) {
+                tracing::error!(error=%e, "Brewing unit {} rejected phase change", unit);
+            }
+        });
+    }
+
+    /// Runs a single brewing phase, slowed down and failed by the injected faults
+    async fn run_phase(
+        state: &Arc<Mutex<StatusState>>,
+        unit: usize,
+        phase: Phase,
+        duration: Duration,
+    ) -> Result<(), Fault> {
+        Self::enter(state, unit, phase);
+        let (faults, clock) = {
+            let st = state.lock().unwrap();
+            (st.faults.clone(), st.clock.clone())
         };
+        clock.sleep(duration.mul_f64(faults.slow_brew_factor)).await;
+        match faults.check(phase) {
+            Some(fault) => Err(fault),
+            None => Ok(()),
+        }
+    }
+
+    /// Handles the business logic for preparing an order
+    async fn prepare_order(
+        unit: usize,
+        order: &OrderMessage,
+        state: &Arc<Mutex<StatusState>>,
+        inventory: &InventoryClient,
+        deducting: &AtomicBool,
+    ) -> Result<(), PrepareError> {
+        tracing::info!(
+            "Processing order {} of type {} on unit {}",
+            order.order_id,
+            order.r#type,
+            unit
+        );
+
+        // Look up ingredient, water and timing requirements of the beverage type
+        let recipe = state.lock().unwrap().recipes.get(&order.r#type).cloned();
+        let Some(recipe) = recipe else {
+            return Err(PrepareError::UnknownType(order.r#type.clone()));
+        };
+        let (beans, milk) = (recipe.beans, recipe.milk);
 
         // Query current stock levels
-        let available = inventory::get_stock().await;
+        let available = inventory.get_stock().await?;
         if available.beans < beans || available.milk < milk {
-            tracing::error!(
-                "Insufficient ingredients for {} (order_id: {})",
-                order.r#type,
-                order.order_id
-            );
-            // TODO: Optionally publish to an order.failed queue
-            return;
+            return Err(PrepareError::InsufficientIngredients);
         }
 
         tracing::info!(
@@ -141,11 +709,13 @@ impl Consumer {
             available.milk
         );
 
-        // Deduct the required ingredients
-        if inventory::deduct_stock(beans, milk).await.is_err() {
-            tracing::error!("Failed to deduct ingredients for order {}", order.order_id);
-            return;
-        }
+        // Deduct the required ingredients; from here on the order is no longer requeued on shutdown
+        deducting.store(true, Ordering::SeqCst);
+        // A redelivered order could deduct twice, so no deduction failure is retried
+        inventory
+            .deduct_stock(beans, milk)
+            .await
+            .map_err(PrepareError::Deduction)?;
 
         tracing::info!(
             "Received order {} (type {}) at {}",
@@ -154,17 +724,26 @@ impl Consumer {
             order.timestamp
         );
 
-        // Simulate preparation delay
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        // Simulate the preparation, one phase at a time
+        let mut phases = vec![
+            (Phase::Grinding, recipe.grind),
+            (Phase::Brewing, recipe.brew),
+        ];
+        if milk > 0 {
+            phases.push((Phase::Frothing, recipe.froth));
+        }
+        phases.push((Phase::Dispensing, recipe.dispense));
+        for (phase, duration) in phases {
+            Self::run_phase(state, unit, phase, duration).await?;
+        }
+        Self::enter(state, unit, Phase::Idle);
+
+        // Every bean portion is one shot through the group head
+        state
+            .lock()
+            .unwrap()
+            .record_usage(beans as u64, recipe.water_ml);
 
-        // Update shared status state upon completion
-        let mut st = state.lock().unwrap();
-        st.last_order_id = order.order_id;
-        st.last_type = order.r#type;
-        st.last_status = "done".to_string();
-        st.last_finished = Utc::now();
-        st.ready = true;
-
-        tracing::info!("Order {} completed", st.last_order_id);
+        Ok(())
     }
 }
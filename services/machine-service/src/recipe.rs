@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+/// Ingredients and phase durations of a beverage
+#[derive(Debug, Clone)]
+pub struct Recipe {
+    pub beans: u32,         // Bean portions, one shot each
+    pub milk: u32,          // Milk portions, frothed if more than zero
+    pub water_ml: u64,      // Water pushed through the group head
+    pub grind: Duration,    // Time spent grinding
+    pub brew: Duration,     // Time spent extracting
+    pub froth: Duration,    // Time spent frothing milk
+    pub dispense: Duration, // Time spent pouring into the cup
+}
+
+/// Recipes of all beverages the machine can prepare, keyed by beverage type
+pub struct Recipes(HashMap<String, Recipe>);
+
+impl Recipes {
+    /// Loads the default recipes with the brewing times overridden by beverage type
+    pub fn new(brew_ms: &BTreeMap<String, u64>) -> anyhow::Result<Self> {
+        let ms = Duration::from_millis;
+        let mut recipes = HashMap::from([
+            (
+                "espresso".to_string(),
+                Recipe {
+                    beans: 1,
+                    milk: 0,
+                    water_ml: 30,
+                    grind: ms(500),
+                    brew: ms(1000),
+                    froth: Duration::ZERO,
+                    dispense: ms(500),
+                },
+            ),
+            (
+                "coffee".to_string(),
+                Recipe {
+                    beans: 2,
+                    milk: 1,
+                    water_ml: 150,
+                    grind: ms(800),
+                    brew: ms(3000),
+                    froth: ms(500),
+                    dispense: ms(1000),
+                },
+            ),
+            (
+                "cappuccino".to_string(),
+                Recipe {
+                    beans: 1,
+                    milk: 2,
+                    water_ml: 30,
+                    grind: ms(500),
+                    brew: ms(1000),
+                    froth: ms(1500),
+                    dispense: ms(500),
+                },
+            ),
+        ]);
+
+        for (name, millis) in brew_ms {
+            let recipe = recipes.get_mut(name).ok_or_else(|| {
+                anyhow::anyhow!("Brewing time given for unknown beverage {}", name)
+            })?;
+            recipe.brew = ms(*millis);
+        }
+
+        Ok(Self(recipes))
+    }
+
+    /// Looks up the recipe of a beverage type
+    pub fn get(&self, r#type: &str) -> Option<&Recipe> {
+        self.0.get(r#type)
+    }
+}
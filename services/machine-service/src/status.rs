@@ -1,45 +1,234 @@
+use crate::clock::Clock;
+use crate::faults::FaultConfig;
+use crate::history::{BrewHistory, BrewRecord};
+use crate::machine::{InvalidTransition, Phase, StateMachine, Transition};
+use crate::maintenance::{Maintenance, MaintenanceKind};
+use crate::metrics::Metrics;
+use crate::recipe::Recipes;
+use crate::stream::{EVENT_BUFFER, StatusEvent};
 use axum::{Extension, Json};
 use chrono::Utc;
+use lapin::ConnectionStatus;
 use serde::Serialize;
+use std::fmt;
 use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 use utoipa::ToSchema;
 
+/// Final status of a processed order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Done,   // The drink was served
+    Failed, // The order could not be prepared
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderStatus::Done => write!(f, "done"),
+            OrderStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
 /// Represents details of the most recent processed order in the status response
 #[derive(Serialize, ToSchema)]
 pub struct LastOrder {
     pub order_id: String, // Unique identifier of the last order
     #[serde(rename = "type")]
     pub r#type: String, // Beverage type: espresso, coffee, cappuccino
-    pub status: String,   // Status of the last order (e.g., "done")
+    pub status: Option<OrderStatus>, // Status of the last order, null before the first order
     pub finished_at: chrono::DateTime<Utc>, // Timestamp when the last order was completed
 }
 
+/// Represents the state of a single brewing unit (group head) in the status response
+#[derive(Serialize, ToSchema)]
+pub struct UnitStatus {
+    pub unit: usize,                        // Number of the brewing unit, starting at 1
+    pub phase: Phase,                       // Current phase of the unit
+    pub phase_since: chrono::DateTime<Utc>, // When the current phase was entered
+    pub current_order_id: Option<String>,   // Order being prepared, if any
+    pub started_at: Option<chrono::DateTime<Utc>>, // When the current order was started
+    pub orders_completed: u64,              // Orders finished by this unit
+    pub transitions: Vec<Transition>,       // Most recent phase changes, oldest first
+}
+
 /// Defines the JSON structure returned by GET /status endpoint
 #[derive(Serialize, ToSchema)]
 pub struct StatusResponse {
-    pub ready: bool,           // Indicates if the machine is ready for a new order
+    pub ready: bool, // Indicates if at least one unit is idle and no maintenance blocks brewing
     pub last_order: LastOrder, // Information about the last processed order
+    pub units: Vec<UnitStatus>, // State of every brewing unit
+    pub maintenance_due: Vec<MaintenanceKind>, // Maintenance cycles blocking new orders
+}
+
+/// Internal state of a single brewing unit
+pub struct UnitState {
+    pub machine: StateMachine, // Phase of the unit and its transitions
+    pub current_order_id: Option<String>, // Order being prepared, if any
+    pub started_at: Option<chrono::DateTime<Utc>>, // When the current order was started
+    pub orders_completed: u64, // Orders finished by this unit
+}
+
+impl UnitState {
+    /// Is the unit idle and not holding an order?
+    pub fn is_free(&self) -> bool {
+        self.current_order_id.is_none() && self.machine.phase() == Phase::Idle
+    }
+
+    /// Reports the unit with the given number for the REST API
+    pub fn status(&self, unit: usize) -> UnitStatus {
+        UnitStatus {
+            unit,
+            phase: self.machine.phase(),
+            phase_since: self.machine.since(),
+            current_order_id: self.current_order_id.clone(),
+            started_at: self.started_at,
+            orders_completed: self.orders_completed,
+            transitions: self.machine.transitions(),
+        }
+    }
 }
 
 /// Internal shared state for tracking machine status
 pub struct StatusState {
-    pub ready: bool,                          // Is the machine ready for a new order?
     pub last_order_id: String,                // ID of the last order processed
     pub last_type: String,                    // Type of the last order processed
-    pub last_status: String,                  // Status of the last order (e.g., "done")
+    pub last_status: Option<OrderStatus>,     // Status of the last order
     pub last_finished: chrono::DateTime<Utc>, // Completion timestamp of the last order
+    pub units: Vec<UnitState>,                // Brewing units, index 0 is unit 1
+    pub history: BrewHistory,                 // Recent brews of all units
+    pub events: broadcast::Sender<StatusEvent>, // Live feed of phase changes and finished orders
+    pub maintenance: Maintenance,             // Usage counters and maintenance schedule
+    pub faults: FaultConfig,                  // Faults injected into the brewing simulation
+    pub clock: Arc<dyn Clock>,                // Time source of the brewing simulation
+    pub recipes: Recipes,                     // Ingredients and phase durations per beverage
+    pub metrics: Arc<Metrics>,                // Prometheus metrics, exposed on /metrics
+    pub broker: Option<ConnectionStatus>,     // RabbitMQ connection while the consumers run
 }
 
 impl StatusState {
-    /// Creates a new StatusState with default initial values
-    pub fn new() -> Self {
+    /// Creates a new StatusState with the given number of idle brewing units
+    pub fn new(
+        units: usize,
+        history: BrewHistory,
+        maintenance: Maintenance,
+        faults: FaultConfig,
+        clock: Arc<dyn Clock>,
+        recipes: Recipes,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let now = clock.now();
         Self {
-            ready: true,                  // Machine starts in a ready state
             last_order_id: String::new(), // No orders processed yet
             last_type: String::new(),     // No type yet
-            last_status: String::new(),   // No status yet
-            last_finished: Utc::now(),    // Default to current time
+            last_status: None,            // No status yet
+            last_finished: now,           // Default to current time
+            units: (0..units)
+                .map(|_| UnitState {
+                    machine: StateMachine::new(now),
+                    current_order_id: None,
+                    started_at: None,
+                    orders_completed: 0,
+                })
+                .collect(),
+            history,
+            events: broadcast::channel(EVENT_BUFFER).0,
+            maintenance,
+            faults,
+            clock,
+            recipes,
+            metrics,
+            broker: None,
+        }
+    }
+
+    /// Is at least one brewing unit idle and able to take a new order?
+    pub fn ready(&self) -> bool {
+        !self.maintenance.blocks_brewing() && self.units.iter().any(|u| u.is_free())
+    }
+
+    /// Assigns an order to a brewing unit unless maintenance blocks brewing or the unit is not idle
+    pub fn try_start(&mut self, unit: usize, order_id: &str) -> bool {
+        let u = &mut self.units[unit - 1];
+        if self.maintenance.blocks_brewing() || !u.is_free() {
+            return false;
+        }
+        u.current_order_id = Some(order_id.to_string());
+        u.started_at = Some(self.clock.now());
+        true
+    }
+
+    /// Moves a brewing unit to the next phase and notifies stream subscribers
+    pub fn advance(&mut self, unit: usize, phase: Phase) -> Result<(), InvalidTransition> {
+        let u = &mut self.units[unit - 1];
+        let transition = u.machine.transition(phase, self.clock.now())?;
+        // Sending only fails when nobody is subscribed
+        let _ = self.events.send(StatusEvent::PhaseChanged {
+            unit,
+            order_id: u.current_order_id.clone(),
+            from: transition.from,
+            to: transition.to,
+            at: transition.at,
+        });
+        Ok(())
+    }
+
+    /// Releases a brewing unit without recording the order, which is retried later
+    pub fn release(&mut self, unit: usize) {
+        let u = &mut self.units[unit - 1];
+        u.current_order_id = None;
+        u.started_at = None;
+    }
+
+    /// Releases a brewing unit and records the finished order in the brew history
+    pub fn finish(
+        &mut self,
+        unit: usize,
+        order_id: String,
+        r#type: String,
+        status: OrderStatus,
+    ) -> BrewRecord {
+        let now = self.clock.now();
+        let u = &mut self.units[unit - 1];
+        u.current_order_id = None;
+        let started_at = u.started_at.take().unwrap_or(now);
+        if status == OrderStatus::Done {
+            u.orders_completed += 1;
         }
+
+        let brew = BrewRecord {
+            order_id: order_id.clone(),
+            r#type: r#type.clone(),
+            unit,
+            started_at,
+            finished_at: now,
+            duration_ms: (now - started_at).num_milliseconds(),
+            outcome: status,
+        };
+        self.history.record(brew.clone());
+
+        // Unknown types share one label to keep the metrics' cardinality bounded
+        let label = match self.recipes.get(&r#type) {
+            Some(_) => r#type.as_str(),
+            None => "unknown",
+        };
+        self.metrics
+            .brews
+            .with_label_values(&[label, &status.to_string()])
+            .inc();
+        self.metrics
+            .brew_duration
+            .with_label_values(&[label])
+            .observe(brew.duration_ms as f64 / 1000.0);
+        let _ = self.events.send(StatusEvent::OrderFinished(brew.clone()));
+
+        self.last_order_id = order_id;
+        self.last_type = r#type;
+        self.last_status = Some(status);
+        self.last_finished = now;
+        brew
     }
 }
 
@@ -61,13 +250,22 @@ pub async fn get_status(
     let last_order = LastOrder {
         order_id: st.last_order_id.clone(),
         r#type: st.last_type.clone(),
-        status: st.last_status.clone(),
+        status: st.last_status,
         finished_at: st.last_finished,
     };
+    // Report every brewing unit
+    let units = st
+        .units
+        .iter()
+        .enumerate()
+        .map(|(i, u)| u.status(i + 1))
+        .collect();
     // Create the response payload
     let resp = StatusResponse {
-        ready: st.ready,
+        ready: st.ready(),
         last_order,
+        units,
+        maintenance_due: st.maintenance.due(),
     };
     Json(resp)
 }
@@ -0,0 +1,186 @@
+use crate::history::BrewRecord;
+use crate::machine::Phase;
+use crate::maintenance::MaintenanceKind;
+use crate::status::{OrderStatus, StatusState};
+use axum::{
+    Extension,
+    extract::ws::{Message, WebSocket, WebSocketUpgrade},
+    response::{
+        IntoResponse,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use utoipa::ToSchema;
+
+/// Number of events buffered for slow subscribers before they start missing events
+pub const EVENT_BUFFER: usize = 256;
+
+/// Event pushed to stream subscribers whenever the machine changes
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StatusEvent {
+    /// A brewing unit moved to another phase
+    PhaseChanged {
+        unit: usize,              // Brewing unit that changed
+        order_id: Option<String>, // Order the unit was working on, if any
+        from: Phase,
+        to: Phase,
+        at: DateTime<Utc>,
+    },
+    /// A brewing unit finished an order
+    OrderFinished(BrewRecord),
+    /// A maintenance cycle became due and blocks brewing until it has run
+    MaintenanceDue {
+        kind: MaintenanceKind,
+        at: DateTime<Utc>,
+    },
+}
+
+impl StatusEvent {
+    /// Name used as the SSE event type
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatusEvent::PhaseChanged { .. } => "phase_changed",
+            StatusEvent::OrderFinished(_) => "order_finished",
+            StatusEvent::MaintenanceDue { .. } => "maintenance_due",
+        }
+    }
+
+    /// Converts the event into per-order progress, if it belongs to an order
+    pub fn progress(&self) -> Option<OrderProgress> {
+        match self {
+            StatusEvent::PhaseChanged {
+                unit,
+                order_id: Some(order_id),
+                to,
+                at,
+                ..
+            } if matches!(
+                to,
+                Phase::Grinding | Phase::Brewing | Phase::Frothing | Phase::Dispensing
+            ) =>
+            {
+                Some(OrderProgress {
+                    order_id: order_id.clone(),
+                    unit: *unit,
+                    stage: Stage::Phase(*to),
+                    at: *at,
+                })
+            }
+            StatusEvent::OrderFinished(brew) => Some(OrderProgress {
+                order_id: brew.order_id.clone(),
+                unit: brew.unit,
+                stage: Stage::Finished(brew.outcome),
+                at: brew.finished_at,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Stage of an order, either a brewing phase or its final status
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(untagged)]
+pub enum Stage {
+    Phase(Phase),
+    Finished(OrderStatus),
+}
+
+/// Progress of a single order, published on the 'order.progress' queue
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderProgress {
+    pub order_id: String,  // Order the progress belongs to
+    pub unit: usize,       // Brewing unit preparing the order
+    pub stage: Stage,      // e.g. "grinding", "brewing", "done", "failed"
+    pub at: DateTime<Utc>, // When the order reached this stage
+}
+
+/// Turns a broadcast receiver into a stream, skipping events missed by slow subscribers
+fn events(rx: broadcast::Receiver<StatusEvent>) -> impl Stream<Item = StatusEvent> {
+    futures_util::stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some((event, rx)),
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Stream subscriber lagged behind, {} events skipped", missed);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+/// GET /status/stream endpoint pushing status events as Server-Sent Events
+#[utoipa::path(
+    get,
+    path = "/status/stream",
+    tag = "Status",
+    responses(
+        (status = 200, description = "Stream of status events, one JSON object per event", body = StatusEvent, content_type = "text/event-stream")
+    )
+)]
+pub async fn get_status_stream(
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+    Extension(shutdown): Extension<CancellationToken>,    // Ends the stream on shutdown
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>> {
+    let rx = state.lock().unwrap().events.subscribe();
+    let stream = events(rx)
+        .take_until(shutdown.cancelled_owned())
+        .map(|event| Event::default().event(event.name()).json_data(&event));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// GET /status/ws endpoint pushing status events over a WebSocket
+#[utoipa::path(
+    get,
+    path = "/status/ws",
+    tag = "Status",
+    responses(
+        (status = 101, description = "Switching to WebSocket, every status event is sent as a JSON text message")
+    )
+)]
+pub async fn get_status_ws(
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+    Extension(shutdown): Extension<CancellationToken>,    // Closes the socket on shutdown
+    ws: WebSocketUpgrade,
+) -> impl IntoResponse {
+    let rx = state.lock().unwrap().events.subscribe();
+    ws.on_upgrade(move |socket| forward(socket, rx, shutdown))
+}
+
+/// Sends status events to a WebSocket client until either side goes away or the service shuts down
+async fn forward(
+    mut socket: WebSocket,
+    rx: broadcast::Receiver<StatusEvent>,
+    shutdown: CancellationToken,
+) {
+    let mut feed = Box::pin(events(rx));
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+            event = feed.next() => {
+                let Some(event) = event else { break };
+                let Ok(text) = serde_json::to_string(&event) else { continue };
+                if socket.send(Message::Text(text.into())).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => {
+                // Incoming messages are ignored, a close or error ends the stream
+                match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use crate::ErrorResponse;
+use crate::machine::{InvalidTransition, Phase};
+use crate::status::{StatusState, UnitStatus};
+use auth::{Principal, Role};
+use axum::{Extension, Json, extract::Path, http::StatusCode};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+/// Errors that can occur when taking a brewing unit out of service or restoring it
+#[derive(Debug, Error)]
+pub enum UnitError {
+    #[error("Unknown brewing unit {0}")]
+    Unknown(usize),
+    #[error("Brewing unit {0} is preparing an order")]
+    Busy(usize),
+    #[error("Brewing unit {0} is neither in error nor out of service")]
+    Running(usize),
+    #[error(transparent)]
+    Transition(#[from] InvalidTransition),
+}
+
+impl From<UnitError> for (StatusCode, Json<ErrorResponse>) {
+    fn from(err: UnitError) -> Self {
+        let status = match err {
+            UnitError::Unknown(_) => StatusCode::NOT_FOUND,
+            _ => StatusCode::CONFLICT,
+        };
+        (
+            status,
+            Json(ErrorResponse {
+                error: err.to_string(),
+            }),
+        )
+    }
+}
+
+impl StatusState {
+    /// Checks the brewing unit exists
+    fn unit(&self, unit: usize) -> Result<(), UnitError> {
+        if unit == 0 || unit > self.units.len() {
+            return Err(UnitError::Unknown(unit));
+        }
+        Ok(())
+    }
+
+    /// Takes an idle or failed brewing unit out of service, it accepts no orders until restored
+    pub fn take_out_of_service(&mut self, unit: usize) -> Result<(), UnitError> {
+        self.unit(unit)?;
+        if self.units[unit - 1].current_order_id.is_some() {
+            return Err(UnitError::Busy(unit));
+        }
+        self.advance(unit, Phase::OutOfService)?;
+        tracing::warn!("Brewing unit {} taken out of service", unit);
+        Ok(())
+    }
+
+    /// Returns a brewing unit that is in error or out of service to idle
+    pub fn restore(&mut self, unit: usize) -> Result<(), UnitError> {
+        self.unit(unit)?;
+        let phase = self.units[unit - 1].machine.phase();
+        if !matches!(phase, Phase::Error | Phase::OutOfService) {
+            return Err(UnitError::Running(unit));
+        }
+        self.advance(unit, Phase::Idle)?;
+        tracing::info!("Brewing unit {} restored", unit);
+        Ok(())
+    }
+}
+
+/// POST /units/{unit}/out-of-service endpoint taking a brewing unit out of service
+#[utoipa::path(
+    post,
+    path = "/units/{unit}/out-of-service",
+    tag = "Admin",
+    params(("unit" = usize, Path, description = "Number of the brewing unit, starting at 1")),
+    responses(
+        (status = 200, description = "Unit taken out of service", body = UnitStatus, content_type = "application/json"),
+        (status = 404, description = "Unknown brewing unit", body = ErrorResponse),
+        (status = 409, description = "Unit is preparing an order or cleaning", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only admins may take units out of service", body = ErrorResponse)
+    )
+)]
+pub async fn post_out_of_service(
+    principal: Principal,                                 // Authenticated caller
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+    Path(unit): Path<usize>,
+) -> Result<Json<UnitStatus>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Admin])?;
+    let mut st = state.lock().unwrap();
+    st.take_out_of_service(unit)?;
+    Ok(Json(st.units[unit - 1].status(unit)))
+}
+
+/// POST /units/{unit}/restore endpoint returning a failed or out-of-service unit to idle
+#[utoipa::path(
+    post,
+    path = "/units/{unit}/restore",
+    tag = "Admin",
+    params(("unit" = usize, Path, description = "Number of the brewing unit, starting at 1")),
+    responses(
+        (status = 200, description = "Unit is idle again", body = UnitStatus, content_type = "application/json"),
+        (status = 404, description = "Unknown brewing unit", body = ErrorResponse),
+        (status = 409, description = "Unit is neither in error nor out of service", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse),
+        (status = 403, description = "Only admins may restore units", body = ErrorResponse)
+    )
+)]
+pub async fn post_restore(
+    principal: Principal,                                 // Authenticated caller
+    Extension(state): Extension<Arc<Mutex<StatusState>>>, // Shared state injected by Axum
+    Path(unit): Path<usize>,
+) -> Result<Json<UnitStatus>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Admin])?;
+    let mut st = state.lock().unwrap();
+    st.restore(unit)?;
+    Ok(Json(st.units[unit - 1].status(unit)))
+}
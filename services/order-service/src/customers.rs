@@ -0,0 +1,293 @@
+use crate::orders::{OrderState, SharedOrders};
+use crate::{ErrorResponse, OrderDesk, OrderRequest, OrderResponse};
+use auth::{AuthError, Principal, Role};
+use axum::{
+    Json,
+    extract::{ConnectInfo, Extension, Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use utoipa::{IntoParams, ToSchema};
+
+/// Orders per page of the order history unless the client asks for another size
+const DEFAULT_PER_PAGE: usize = 20;
+/// Largest page of the order history
+const MAX_PER_PAGE: usize = 100;
+
+/// A regular of the coffee shop; the id is the subject of their JWT or API key
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Customer {
+    pub id: String,
+    pub name: String,
+    pub preferred_drink: Option<OrderRequest>, // Their usual, re-ordered in one call
+}
+
+/// Request payload for creating or updating a customer
+#[derive(Deserialize, ToSchema)]
+pub struct CustomerRequest {
+    name: String,
+    #[serde(default)]
+    preferred_drink: Option<OrderRequest>, // Checked against the menu
+}
+
+/// Profiles of the customers, by id
+pub type SharedCustomers = Arc<Mutex<HashMap<String, Customer>>>;
+
+/// Query parameters for GET /customers/{id}/orders
+#[derive(Deserialize, IntoParams)]
+pub struct HistoryQuery {
+    /// Page to return, starting at 1
+    pub page: Option<usize>,
+    /// Orders per page, at most 100
+    pub per_page: Option<usize>,
+}
+
+/// An order in a customer's history
+#[derive(Serialize, ToSchema)]
+pub struct OrderSummary {
+    pub order_id: String,
+    #[serde(rename = "type")]
+    pub drink_type: String,
+    pub size: Option<String>, // Null for the default size
+    pub extras: Vec<String>,
+    pub state: OrderState,
+    pub placed_at: DateTime<Utc>,
+    pub total_cents: u64, // Charged price including taxes
+    pub currency: String,
+}
+
+/// A page of a customer's order history, newest orders first
+#[derive(Serialize, ToSchema)]
+pub struct OrderHistory {
+    pub customer_id: String,
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize, // Remembered orders of the customer across all pages
+    pub orders: Vec<OrderSummary>,
+}
+
+/// Fails unless the caller is the customer or a barista serving them at the counter
+fn act_for(principal: &Principal, id: &str) -> Result<(), AuthError> {
+    principal.require(&[Role::Customer, Role::Barista])?;
+    if principal.has_any(&[Role::Barista]) || principal.subject == id {
+        Ok(())
+    } else {
+        Err(AuthError::Forbidden)
+    }
+}
+
+/// 404 Not Found with a message
+fn not_found(error: String) -> (StatusCode, Json<ErrorResponse>) {
+    (StatusCode::NOT_FOUND, Json(ErrorResponse { error }))
+}
+
+/// Handler for creating or updating a customer's name and preferred drink
+#[utoipa::path(
+    put,
+    path = "/customers/{id}",
+    tag = "Customers",
+    params(("id" = String, Path, description = "Customer id, the subject of their credentials")),
+    request_body(content = CustomerRequest, description = "Name and preferred drink", content_type = "application/json"),
+    responses(
+        (status = 200, description = "The stored customer", body = Customer, content_type = "application/json"),
+        (status = 400, description = "Empty name or a preferred drink that is not on the menu", body = ErrorResponse, content_type = "application/json"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
+        (status = 403, description = "Customers may only change their own profile", body = ErrorResponse, content_type = "application/json")
+    )
+)]
+pub async fn put_customer(
+    // Caller, the customer themselves or a barista
+    principal: Principal,
+    // Inject the customers to store the profile in
+    Extension(customers): Extension<SharedCustomers>,
+    // Inject the order desk, whose menu the preferred drink is checked against
+    Extension(desk): Extension<OrderDesk>,
+    Path(id): Path<String>,
+    Json(payload): Json<CustomerRequest>,
+) -> Result<Json<Customer>, (StatusCode, Json<ErrorResponse>)> {
+    act_for(&principal, &id)?;
+
+    let name = payload.name.trim();
+    if name.is_empty() {
+        let err = ErrorResponse {
+            error: "The name must not be empty".into(),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(err)));
+    }
+    // Refuse a usual that could never be ordered
+    if let Some(drink) = &payload.preferred_drink {
+        desk.menu
+            .price(
+                "",
+                &drink.drink_type,
+                drink.size.as_deref(),
+                &drink.extras,
+                Utc::now(),
+            )
+            .map_err(|error| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error })))?;
+    }
+
+    let customer = Customer {
+        id: id.clone(),
+        name: name.into(),
+        preferred_drink: payload.preferred_drink,
+    };
+    customers.lock().unwrap().insert(id, customer.clone());
+    Ok(Json(customer))
+}
+
+/// Handler for fetching a customer's profile
+#[utoipa::path(
+    get,
+    path = "/customers/{id}",
+    tag = "Customers",
+    params(("id" = String, Path, description = "Customer id, the subject of their credentials")),
+    responses(
+        (status = 200, description = "Name and preferred drink of the customer", body = Customer, content_type = "application/json"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
+        (status = 403, description = "Customers may only see their own profile", body = ErrorResponse, content_type = "application/json"),
+        (status = 404, description = "Unknown customer", body = ErrorResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_customer(
+    // Caller, the customer themselves or a barista
+    principal: Principal,
+    // Inject the customers to look the profile up in
+    Extension(customers): Extension<SharedCustomers>,
+    Path(id): Path<String>,
+) -> Result<Json<Customer>, (StatusCode, Json<ErrorResponse>)> {
+    act_for(&principal, &id)?;
+    let customers = customers.lock().unwrap();
+    match customers.get(&id) {
+        Some(customer) => Ok(Json(customer.clone())),
+        None => Err(not_found(format!("Customer {} not found", id))),
+    }
+}
+
+/// Handler for a page of a customer's order history
+#[utoipa::path(
+    get,
+    path = "/customers/{id}/orders",
+    tag = "Customers",
+    params(("id" = String, Path, description = "Customer id, the subject of their credentials"), HistoryQuery),
+    responses(
+        (status = 200, description = "Remembered orders of the customer, newest first", body = OrderHistory, content_type = "application/json"),
+        (status = 400, description = "Page or page size out of range", body = ErrorResponse, content_type = "application/json"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
+        (status = 403, description = "Customers may only see their own orders", body = ErrorResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_customer_orders(
+    // Caller, the customer themselves or a barista
+    principal: Principal,
+    // Inject the orders to page through
+    Extension(orders): Extension<SharedOrders>,
+    Path(id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<OrderHistory>, (StatusCode, Json<ErrorResponse>)> {
+    act_for(&principal, &id)?;
+
+    let page = query.page.unwrap_or(1);
+    let per_page = query.per_page.unwrap_or(DEFAULT_PER_PAGE);
+    if page == 0 || per_page == 0 || per_page > MAX_PER_PAGE {
+        let err = ErrorResponse {
+            error: format!(
+                "page must be at least 1 and per_page between 1 and {}",
+                MAX_PER_PAGE
+            ),
+        };
+        return Err((StatusCode::BAD_REQUEST, Json(err)));
+    }
+
+    let orders = orders.lock().unwrap();
+    let total = orders.of_customer(&id).count();
+    let summaries = orders
+        .of_customer(&id)
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .map(|record| OrderSummary {
+            order_id: record.order_id.clone(),
+            drink_type: record.drink_type.clone(),
+            size: record.size.clone(),
+            extras: record.extras.clone(),
+            state: record.state,
+            placed_at: record.placed_at,
+            total_cents: record.receipt.total_cents,
+            currency: record.receipt.currency.clone(),
+        })
+        .collect();
+    Ok(Json(OrderHistory {
+        customer_id: id,
+        page,
+        per_page,
+        total,
+        orders: summaries,
+    }))
+}
+
+/// Handler for re-ordering a customer's usual: their preferred drink, or else their latest order
+#[utoipa::path(
+    post,
+    path = "/customers/{id}/orders/usual",
+    tag = "Customers",
+    params(("id" = String, Path, description = "Customer id, the subject of their credentials")),
+    responses(
+        (status = 202, description = "Order accepted", body = OrderResponse, content_type = "application/json"),
+        (status = 400, description = "The usual is no longer on the menu", body = ErrorResponse, content_type = "application/json"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
+        (status = 402, description = "The payment was declined", body = ErrorResponse, content_type = "application/json"),
+        (status = 403, description = "Customers may only re-order their own usual", body = ErrorResponse, content_type = "application/json"),
+        (status = 404, description = "The customer has neither a preferred drink nor a remembered order", body = ErrorResponse, content_type = "application/json"),
+        (status = 429, description = "Too many orders from this client or customer", body = ErrorResponse, content_type = "application/json",
+            headers(("Retry-After" = u64, description = "Seconds until an order will be accepted again"))),
+        (status = 500, description = "Internal server error", body = ErrorResponse, content_type = "application/json"),
+        (status = 502, description = "The payment provider failed", body = ErrorResponse, content_type = "application/json"),
+        (status = 503, description = "Too many orders are waiting for a machine", body = ErrorResponse, content_type = "application/json",
+            headers(("Retry-After" = u64, description = "Seconds to wait before trying again"))),
+        (status = 504, description = "The payment provider did not answer in time", body = ErrorResponse, content_type = "application/json")
+    )
+)]
+pub async fn post_usual_order(
+    // Caller, the customer themselves or a barista ordering for them at the counter
+    principal: Principal,
+    // Address of the client, for the rate limit per IP
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    // Inject the customers to look the preferred drink up in
+    Extension(customers): Extension<SharedCustomers>,
+    // Inject the limits, producer, metrics, orders, estimator, menu and payments
+    Extension(desk): Extension<OrderDesk>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, Json<OrderResponse>), Response> {
+    act_for(&principal, &id).map_err(IntoResponse::into_response)?;
+
+    let preferred = customers
+        .lock()
+        .unwrap()
+        .get(&id)
+        .and_then(|customer| customer.preferred_drink.clone());
+    let usual = preferred.or_else(|| {
+        desk.orders
+            .lock()
+            .unwrap()
+            .of_customer(&id)
+            .next()
+            .map(|record| OrderRequest {
+                drink_type: record.drink_type.clone(),
+                size: record.size.clone(),
+                extras: record.extras.clone(),
+            })
+    });
+    let Some(usual) = usual else {
+        let err = not_found(format!("Customer {} has no usual drink yet", id));
+        return Err(err.into_response());
+    };
+
+    let ip = desk.limits.client_ip(peer, &headers);
+    crate::place_order(desk, &principal.subject, id, ip, usual).await
+}
@@ -0,0 +1,75 @@
+use crate::config::EtaConfig;
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::BTreeMap;
+
+/// Estimates when orders will be ready from the orders ahead of them, the brewing units sharing
+/// the work and the preparation time of each drink
+pub struct Estimator {
+    prep: BTreeMap<String, TimeDelta>, // Preparation time by beverage type
+    average: TimeDelta,                // Preparation time of an order of unknown type
+}
+
+impl Estimator {
+    pub fn new(config: &EtaConfig) -> Self {
+        // The machine runs its recipes `time_scale` times faster, at 0 every drink is ready at once
+        let scaled = |secs: f64| {
+            if config.time_scale > 0.0 {
+                secs_delta(secs / config.time_scale)
+            } else {
+                TimeDelta::zero()
+            }
+        };
+        let prep: BTreeMap<String, TimeDelta> = config
+            .prep_secs
+            .iter()
+            .map(|(r#type, secs)| (r#type.clone(), scaled(*secs)))
+            .collect();
+        let total: f64 = config.prep_secs.values().sum();
+        let average = scaled(total / config.prep_secs.len().max(1) as f64);
+        Self { prep, average }
+    }
+
+    /// Preparation time of a drink, the average for types without their own
+    fn prep_time(&self, r#type: &str) -> TimeDelta {
+        self.prep.get(r#type).copied().unwrap_or(self.average)
+    }
+
+    /// Ready time of an order waiting behind `ahead` others on `units` brewing units:
+    /// every full round of orders ahead delays it by an average preparation time.
+    /// None if the estimate is out of the range of dates
+    pub fn queued(
+        &self,
+        r#type: &str,
+        ahead: u32,
+        units: usize,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let rounds = i32::try_from(ahead as usize / units.max(1)).ok()?;
+        let wait = self
+            .average
+            .checked_mul(rounds)?
+            .checked_add(&self.prep_time(r#type))?;
+        now.checked_add_signed(wait)
+    }
+
+    /// Ready time of an order that has been prepared since `started_at`,
+    /// none if it is out of the range of dates
+    pub fn in_progress(
+        &self,
+        r#type: &str,
+        started_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let ready_at = started_at.checked_add_signed(self.prep_time(r#type))?;
+        Some(ready_at.max(now))
+    }
+}
+
+/// Converts seconds to a TimeDelta with millisecond precision, the longest one if out of range
+fn secs_delta(secs: f64) -> TimeDelta {
+    let millis = (secs * 1000.0).round();
+    if !(0.0..i64::MAX as f64).contains(&millis) {
+        return TimeDelta::MAX;
+    }
+    TimeDelta::try_milliseconds(millis as i64).unwrap_or(TimeDelta::MAX)
+}
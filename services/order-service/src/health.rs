@@ -0,0 +1,34 @@
+use crate::SharedProducer;
+use axum::{Json, extract::Extension, http::StatusCode};
+use probes::{Check, HealthResponse};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How long the readiness check waits for the producer before reporting the broker as down
+const PRODUCER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Handler for GET /health/ready, up while orders can be published to RabbitMQ
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "Health",
+    responses(
+        (status = 200, description = "The service accepts orders", body = HealthResponse, content_type = "application/json"),
+        (status = 503, description = "A dependency is unavailable", body = HealthResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_ready(
+    // Inject shared RabbitMQ producer to check its connection
+    Extension(producer): Extension<SharedProducer>,
+) -> (StatusCode, Json<HealthResponse>) {
+    // Orders hold the producer until RabbitMQ confirms them, a slow broker keeps it busy
+    let broker = match tokio::time::timeout(PRODUCER_TIMEOUT, producer.lock()).await {
+        Ok(prod) if prod.is_connected() => Check::up("Connected, publishing to order.placed"),
+        Ok(_) => Check::down("Connection to RabbitMQ lost"),
+        Err(_) => Check::down(format!(
+            "Producer busy for longer than {:?}, RabbitMQ is not confirming orders",
+            PRODUCER_TIMEOUT
+        )),
+    };
+    HealthResponse::from_checks(BTreeMap::from([("rabbitmq".to_string(), broker)]))
+}
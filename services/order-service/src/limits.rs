@@ -0,0 +1,163 @@
+use crate::ErrorResponse;
+use crate::config::{LimitsConfig, RateLimit};
+use crate::load::SharedLoad;
+use axum::{
+    Json,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Number of tracked callers above which idle ones are forgotten
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Why an order was turned away before it was published
+#[derive(Debug)]
+pub enum Throttled {
+    RateLimited(Duration), // The caller placed too many orders, retry after the duration
+    QueueFull(Duration),   // Too many orders are waiting for a machine
+}
+
+impl IntoResponse for Throttled {
+    fn into_response(self) -> Response {
+        let (status, retry_after, error) = match self {
+            Throttled::RateLimited(retry_after) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                retry_after,
+                "Too many orders, please slow down",
+            ),
+            Throttled::QueueFull(retry_after) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                retry_after,
+                "The baristas are swamped, please try again shortly",
+            ),
+        };
+        // Retry-After is given in whole seconds, rounded up so clients never retry too early
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        let mut response = (
+            status,
+            Json(ErrorResponse {
+                error: error.into(),
+            }),
+        )
+            .into_response();
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(secs.max(1)));
+        response
+    }
+}
+
+/// Tokens left in a caller's bucket
+struct Bucket {
+    tokens: f64,      // Orders the caller may place right now
+    updated: Instant, // When the tokens were last refilled
+}
+
+/// Token bucket rate limiter per caller: `burst` orders at once, refilled at `per_minute`;
+/// a limit of 0 lets every order through
+struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<String, Bucket>>, // Buckets by caller
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the caller's bucket, or tells how long until the next one
+    fn check(&self, caller: &str, now: Instant) -> Result<(), Duration> {
+        if self.limit.burst == 0 || self.limit.per_minute == 0 {
+            return Ok(());
+        }
+        let burst = f64::from(self.limit.burst);
+        let per_sec = f64::from(self.limit.per_minute) / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_THRESHOLD {
+            // Callers whose bucket has refilled completely are the same as unknown ones
+            buckets.retain(|_, b| {
+                b.tokens + now.duration_since(b.updated).as_secs_f64() * per_sec < burst
+            });
+        }
+        let bucket = buckets.entry(caller.to_string()).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * per_sec;
+        bucket.tokens = (bucket.tokens + refilled).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+        }
+    }
+}
+
+/// Rate limits per client address and per customer, and backpressure by queue depth
+pub struct Limits {
+    per_ip: RateLimiter,        // Orders per client address
+    per_customer: RateLimiter,  // Orders per API key or JWT subject
+    trust_forwarded_for: bool,  // Client address from X-Forwarded-For
+    max_queue_depth: u32,       // Pending orders above which orders are refused, 0 = no limit
+    queue_full_retry: Duration, // Retry-After sent while the queue is full
+    load: SharedLoad,           // Queue depth, polled in the background
+}
+
+impl Limits {
+    pub fn new(config: &LimitsConfig, load: SharedLoad) -> Self {
+        Self {
+            per_ip: RateLimiter::new(config.per_ip),
+            per_customer: RateLimiter::new(config.per_customer),
+            trust_forwarded_for: config.trust_forwarded_for,
+            max_queue_depth: config.max_queue_depth,
+            queue_full_retry: Duration::from_secs(config.queue_full_retry_secs),
+            load,
+        }
+    }
+
+    /// Address of the client, the last X-Forwarded-For entry if the proxy in front is trusted;
+    /// proxies append to the header, so earlier entries are whatever the client sent
+    pub fn client_ip(&self, peer: SocketAddr, headers: &HeaderMap) -> IpAddr {
+        let forwarded = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .next_back()
+            .filter(|_| self.trust_forwarded_for)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|ip| ip.trim().parse().ok());
+        forwarded.unwrap_or(peer.ip())
+    }
+
+    /// Admits an order unless the queue is full or the client or customer is over its limit;
+    /// customers are only limited when they are authenticated
+    pub fn check(&self, ip: IpAddr, customer: Option<&str>) -> Result<(), Throttled> {
+        if self.max_queue_depth > 0 && self.load.queue_depth() >= self.max_queue_depth {
+            return Err(Throttled::QueueFull(self.queue_full_retry));
+        }
+        let now = Instant::now();
+        self.per_ip
+            .check(&ip.to_string(), now)
+            .map_err(Throttled::RateLimited)?;
+        if let Some(customer) = customer {
+            self.per_customer
+                .check(customer, now)
+                .map_err(Throttled::RateLimited)?;
+        }
+        Ok(())
+    }
+}
+
+/// Limits shared by all requests
+pub type SharedLimits = Arc<Limits>;
@@ -0,0 +1,113 @@
+use crate::config::Config;
+use crate::rabbitmq;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// How busy the coffee shop is, refreshed in the background
+pub struct ShopLoad {
+    queue_depth: AtomicU32,    // Orders waiting in order.placed
+    active_units: AtomicUsize, // Brewing units able to take orders
+    busy_units: AtomicUsize,   // Brewing units preparing an order
+}
+
+impl ShopLoad {
+    /// Starts with an empty queue and the configured number of idle units
+    pub fn new(units: usize) -> Self {
+        Self {
+            queue_depth: AtomicU32::new(0),
+            active_units: AtomicUsize::new(units),
+            busy_units: AtomicUsize::new(0),
+        }
+    }
+
+    /// Orders waiting in order.placed when the queue was last polled
+    pub fn queue_depth(&self) -> u32 {
+        self.queue_depth.load(Ordering::Relaxed)
+    }
+
+    /// Brewing units able to take orders, at least 1 so estimates stay finite
+    pub fn active_units(&self) -> usize {
+        self.active_units.load(Ordering::Relaxed).max(1)
+    }
+
+    /// Orders the machine is preparing right now
+    pub fn busy_units(&self) -> usize {
+        self.busy_units.load(Ordering::Relaxed)
+    }
+}
+
+/// Load shared by the rate limits and the ETA estimation
+pub type SharedLoad = Arc<ShopLoad>;
+
+/// Subset of the machine service's GET /status response
+#[derive(Deserialize)]
+struct MachineStatus {
+    units: Vec<MachineUnit>,
+}
+
+/// A brewing unit in the machine service's GET /status response
+#[derive(Deserialize)]
+struct MachineUnit {
+    phase: String,                    // Phase, e.g. brewing or out_of_service
+    current_order_id: Option<String>, // Order being prepared, if any
+}
+
+/// Periodically reads the depth of the order queue and, if the machine service is configured,
+/// its brewing units until shutdown; the last known values are kept while a source is unreachable
+pub async fn poll(load: SharedLoad, config: Arc<Config>, shutdown: CancellationToken) {
+    let interval = Duration::from_secs(config.limits.queue_poll_secs);
+    let http = reqwest::Client::new();
+    loop {
+        match rabbitmq::fetch_queue_length(&config).await {
+            Ok(depth) => load.queue_depth.store(depth, Ordering::Relaxed),
+            Err(e) => warn!(error=%e, "Failed to read the order queue depth"),
+        }
+        if let Some(url) = &config.eta.machine_url {
+            match fetch_machine_status(&http, url).await {
+                Ok(status) => {
+                    let active = status
+                        .units
+                        .iter()
+                        .filter(|unit| takes_orders(&unit.phase))
+                        .count();
+                    let busy = status
+                        .units
+                        .iter()
+                        .filter(|unit| unit.current_order_id.is_some())
+                        .count();
+                    load.active_units.store(active, Ordering::Relaxed);
+                    load.busy_units.store(busy, Ordering::Relaxed);
+                }
+                Err(e) => warn!(error=%e, "Failed to read the machine status"),
+            }
+        }
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// Can a unit in this phase take orders, now or once it has finished the current one?
+fn takes_orders(phase: &str) -> bool {
+    !matches!(phase, "cleaning" | "error" | "out_of_service")
+}
+
+/// Fetches the brewing units of the machine service via GET /status
+async fn fetch_machine_status(
+    http: &reqwest::Client,
+    base_url: &str,
+) -> anyhow::Result<MachineStatus> {
+    let url = format!("{}/status", base_url.trim_end_matches('/'));
+    Ok(http
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<MachineStatus>()
+        .await?)
+}
@@ -1,28 +1,66 @@
-use axum::{Json, Router, extract::Extension, http::StatusCode};
+use axum::{
+    Json, Router,
+    extract::{ConnectInfo, Extension},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use clap::Parser;
+use config::Config;
 use serde::{Deserialize, Serialize};
-use std::net::Ipv4Addr;
-use std::time::Duration;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use tokio::time::sleep;
-use tracing::{error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 use utoipa_axum::router::OpenApiRouter;
 use uuid::Uuid;
 
 use utoipa::{OpenApi, ToSchema};
 use utoipa_swagger_ui::SwaggerUi;
 
+mod config;
+mod customers;
+mod eta;
+mod health;
+mod limits;
+mod load;
+mod menu;
+mod metrics;
+mod orders;
+mod payments;
+mod progress;
 mod rabbitmq;
 
 // Type alias for shared, thread-safe access to the RabbitMQ producer
 type SharedProducer = Arc<Mutex<rabbitmq::Producer>>;
 
-// Request payload for placing an order
-#[derive(Deserialize, ToSchema)]
+// Everything placing an order depends on, shared with the handler as one extension
+#[derive(Clone)]
+struct OrderDesk {
+    limits: limits::SharedLimits,      // Rate limits and backpressure
+    producer: SharedProducer,          // Publishes paid orders
+    metrics: Arc<metrics::Metrics>,    // Counts accepted and rejected orders
+    orders: orders::SharedOrders,      // Who placed an order and how far it has got
+    load: load::SharedLoad,            // Shop load the ready time is estimated from
+    estimator: Arc<eta::Estimator>,    // Estimates the ready time
+    menu: Arc<menu::Menu>,             // Prices the order
+    payments: Arc<payments::Payments>, // Charges the order
+}
+
+// Request payload for placing an order, also a customer's preferred drink
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 struct OrderRequest {
     #[serde(rename = "type")]
     drink_type: String,
+    #[serde(default)]
+    size: Option<String>, // Cup size, the menu's default size if not given
+    #[serde(default)]
+    #[schema(max_items = 10)]
+    extras: Vec<String>, // Extras like extra_shot or oat_milk, each charged separately
 }
 
 // Successful order response structure
@@ -30,6 +68,9 @@ struct OrderRequest {
 struct OrderResponse {
     message: String,
     order_id: String,
+    estimated_ready_at: Option<chrono::DateTime<chrono::Utc>>, // Refreshed by GET /order/{id}
+    total_cents: u64,                                          // Including taxes, see the receipt
+    currency: String,                                          // ISO 4217 code of the total
 }
 
 // Error response structure
@@ -38,13 +79,52 @@ struct ErrorResponse {
     error: String,
 }
 
+impl From<auth::AuthError> for ErrorResponse {
+    fn from(err: auth::AuthError) -> Self {
+        ErrorResponse {
+            error: err.to_string(),
+        }
+    }
+}
+
 // Define OpenAPI documentation for the API
 #[derive(OpenApi)]
 #[openapi(
-    paths(post_order),
-    components(schemas(OrderRequest, OrderResponse, ErrorResponse)),
+    paths(
+        post_order,
+        orders::get_order,
+        orders::get_receipt,
+        progress::get_order_stream,
+        customers::put_customer,
+        customers::get_customer,
+        customers::get_customer_orders,
+        customers::post_usual_order,
+        probes::get_live,
+        health::get_ready
+    ),
+    components(schemas(
+        OrderRequest,
+        OrderResponse,
+        ErrorResponse,
+        orders::OrderState,
+        orders::OrderStatusResponse,
+        orders::PaymentState,
+        menu::Receipt,
+        menu::ReceiptLine,
+        menu::TaxLine,
+        progress::OrderProgress,
+        customers::Customer,
+        customers::CustomerRequest,
+        customers::OrderSummary,
+        customers::OrderHistory,
+        probes::HealthResponse,
+        probes::HealthStatus,
+        probes::Check
+    )),
     tags(
-        (name = "Orders", description = "Order APIs")
+        (name = "Orders", description = "Order APIs"),
+        (name = "Customers", description = "Profiles, order history and usual drinks of regulars"),
+        (name = "Health", description = "Liveness and readiness probes")
     )
 )]
 struct ApiDoc;
@@ -52,15 +132,22 @@ struct ApiDoc;
 // Main entry point of the application
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing subscriber for logging with environment-based level filter
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    // Load and validate the configuration from file, environment and command line
+    let cli = config::Cli::parse();
+    let config = Config::load(&cli)?;
+    if cli.print_config {
+        print!("{}", toml::to_string_pretty(&config.redacted())?);
+        return Ok(());
+    }
+    let config = Arc::new(config);
+
+    // Initialize logging with environment-based level filter and optional OTLP span export
+    let tracer_provider = telemetry::init("order-service")?;
 
     // Initialize the RabbitMQ producer, retrying until successful
     let producer = loop {
         let retry_delay = Duration::from_secs(1);
-        match rabbitmq::Producer::init().await {
+        match rabbitmq::Producer::init(&config.amqp).await {
             Ok(p) => break p,
             Err(err) => {
                 error!(error=%err, "Init of RabbitMQ producer failed. Retrying in {:?}.", retry_delay);
@@ -71,10 +158,120 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Wrap the producer in an Arc<Mutex<>> for shared, async-safe usage in handlers
     let shared_producer = Arc::new(Mutex::new(producer));
 
+    // Verifies the JWTs and API keys of callers
+    let authenticator = Arc::new(auth::Authenticator::new(&config.auth)?);
+    if !config.auth.enabled {
+        warn!("Authentication is disabled, every caller is treated as admin");
+    }
+    let orders = orders::SharedOrders::default();
+    let customers = customers::SharedCustomers::default();
+
+    // Queue depth and brewing units, polled in the background
+    let load = Arc::new(load::ShopLoad::new(config.eta.units));
+
+    // Rate limits per client and customer, and backpressure once too many orders are waiting
+    let limits = Arc::new(limits::Limits::new(&config.limits, load.clone()));
+
+    // Estimates when orders will be ready
+    let estimator = Arc::new(eta::Estimator::new(&config.eta));
+
+    // Prices orders and itemizes their receipts
+    let menu = Arc::new(menu::Menu::new(&config.menu));
+
+    // Charges orders before they are published and refunds them once they fail
+    let payments = Arc::new(payments::Payments::new(&config.payments));
+
+    // Prometheus metrics, exposed on /metrics
+    let metrics = Arc::new(metrics::Metrics::new()?);
+
+    // Cancelled on SIGINT or SIGTERM, stops the HTTP server, the streams and the progress consumer
+    let shutdown = CancellationToken::new();
+    tokio::spawn(signals::cancel_on_shutdown(shutdown.clone()));
+
+    // Keep the load the backpressure and the estimates are based on up to date
+    tokio::spawn(load::poll(load.clone(), config.clone(), shutdown.clone()));
+
+    // Relay order progress from the coffee machine to stream subscribers, reconnecting on failure
+    let progress_tx: progress::ProgressSender =
+        tokio::sync::broadcast::channel(progress::PROGRESS_BUFFER).0;
+    let consumer_tx = progress_tx.clone();
+    let consumer_orders = orders.clone();
+    let consumer_shutdown = shutdown.clone();
+    let consumer_config = config.clone();
+    let progress_consumer = tokio::spawn(async move {
+        while !consumer_shutdown.is_cancelled() {
+            let retry_delay = Duration::from_secs(1);
+            if let Err(err) = rabbitmq::consume_progress(
+                &consumer_config.amqp,
+                consumer_orders.clone(),
+                consumer_tx.clone(),
+                consumer_shutdown.clone(),
+            )
+            .await
+            {
+                error!(error=%err, "Progress consumer failed. Retrying in {:?}.", retry_delay);
+                tokio::select! {
+                    _ = consumer_shutdown.cancelled() => {}
+                    _ = sleep(retry_delay) => {}
+                }
+            }
+        }
+    });
+
+    // Refund the orders the machine dead-letters to order.failed, reconnecting on failure
+    let refund_orders = orders.clone();
+    let refund_payments = payments.clone();
+    let refund_metrics = metrics.clone();
+    let refund_shutdown = shutdown.clone();
+    let refund_config = config.clone();
+    let refund_consumer = tokio::spawn(async move {
+        while !refund_shutdown.is_cancelled() {
+            let retry_delay = Duration::from_secs(1);
+            if let Err(err) = rabbitmq::consume_failed(
+                &refund_config.amqp,
+                refund_orders.clone(),
+                refund_payments.clone(),
+                refund_metrics.clone(),
+                refund_shutdown.clone(),
+            )
+            .await
+            {
+                error!(error=%err, "Refund consumer failed. Retrying in {:?}.", retry_delay);
+                tokio::select! {
+                    _ = refund_shutdown.cancelled() => {}
+                    _ = sleep(retry_delay) => {}
+                }
+            }
+        }
+    });
+
+    // Bundle what placing an order depends on
+    let desk = OrderDesk {
+        limits,
+        producer: shared_producer.clone(),
+        metrics: metrics.clone(),
+        orders: orders.clone(),
+        load: load.clone(),
+        estimator: estimator.clone(),
+        menu,
+        payments,
+    };
+
     // Build OpenAPI router and extract the spec for Swagger UI
     let (api_router, api_spec) = OpenApiRouter::with_openapi(ApiDoc::openapi())
         .routes(utoipa_axum::routes![post_order])
         .routes(utoipa_axum::routes![get_queue_length])
+        .routes(utoipa_axum::routes![orders::get_order])
+        .routes(utoipa_axum::routes![orders::get_receipt])
+        .routes(utoipa_axum::routes![progress::get_order_stream])
+        .routes(utoipa_axum::routes![
+            customers::put_customer,
+            customers::get_customer
+        ])
+        .routes(utoipa_axum::routes![customers::get_customer_orders])
+        .routes(utoipa_axum::routes![customers::post_usual_order])
+        .routes(utoipa_axum::routes![probes::get_live])
+        .routes(utoipa_axum::routes![health::get_ready])
         .split_for_parts();
 
     // Construct the full application router
@@ -83,20 +280,61 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", api_spec.clone()))
         // Mount the API routes
         .merge(api_router)
+        // Expose Prometheus metrics
+        .route("/metrics", get(metrics::get_metrics))
+        // Trace every request, continuing the caller's trace
+        .layer(axum::middleware::from_fn(telemetry::trace_request))
         // Add shared producer as an extension for handlers to access
-        .layer(Extension(shared_producer));
-
-    // Bind to 0.0.0.0:8080 and start serving
+        .layer(Extension(shared_producer.clone()))
+        // Add the progress sender for the order stream
+        .layer(Extension(progress_tx))
+        // Add the metrics handlers record to
+        .layer(Extension(metrics))
+        // Let long-lived streams end on shutdown
+        .layer(Extension(shutdown.clone()))
+        // Add the configuration, the queue length is read via the management API
+        .layer(Extension(config.clone()))
+        // Add the customers of recent orders, they may only follow their own
+        .layer(Extension(orders.clone()))
+        // Add the authenticator the handlers identify their callers with
+        .layer(Extension(authenticator))
+        // Add the shop load and the estimator for the ready time of orders
+        .layer(Extension(load))
+        .layer(Extension(estimator))
+        // Add the limits, menu and payments new orders go through
+        .layer(Extension(desk))
+        // Add the profiles of the customers
+        .layer(Extension(customers));
 
-    let port: u16 = std::env::var("SERVICE_PORT")
-        .unwrap_or_else(|_| "8080".into())
-        .parse()?;
-    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    // Bind to 0.0.0.0 on the configured port (default 8080) and start serving
+    let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.port));
     let listener = TcpListener::bind(&addr).await.unwrap();
     info!("Listening on {}", addr);
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    // Stop accepting requests once shutdown begins; in-flight orders are still published
+    // The client address is needed for the rate limit per IP
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+    .await
+    .unwrap();
+
+    // Flush outstanding publisher confirms and close the RabbitMQ connections
+    if let Err(e) = shared_producer.lock().await.close().await {
+        error!("Closing RabbitMQ producer failed: {e}");
+    }
+    if let Err(e) = progress_consumer.await {
+        error!("Progress consumer stopped abnormally: {e}");
+    }
+    if let Err(e) = refund_consumer.await {
+        error!("Refund consumer stopped abnormally: {e}");
+    }
+    // Flush spans that have not been exported yet
+    if let Some(Err(e)) = tracer_provider.map(|provider| provider.shutdown()) {
+        error!("Flushing spans failed: {e}");
+    }
+    info!("Shutdown complete");
 
     Ok(())
 }
@@ -112,51 +350,222 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     ),
     responses(
             (status = 202, description = "Order accepted", body = OrderResponse, content_type = "application/json"),
-            (status = 400, description = "Invalid drink type", body = ErrorResponse, content_type = "application/json"),
-            (status = 500, description = "Internal server error", body = ErrorResponse, content_type = "application/json")
+            (status = 400, description = "Invalid drink type, size or extra", body = ErrorResponse, content_type = "application/json"),
+            (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
+            (status = 402, description = "The payment was declined", body = ErrorResponse, content_type = "application/json"),
+            (status = 403, description = "Only customers and baristas place orders", body = ErrorResponse, content_type = "application/json"),
+            (status = 429, description = "Too many orders from this client or customer", body = ErrorResponse, content_type = "application/json",
+                headers(("Retry-After" = u64, description = "Seconds until an order will be accepted again"))),
+            (status = 500, description = "Internal server error", body = ErrorResponse, content_type = "application/json"),
+            (status = 502, description = "The payment provider failed", body = ErrorResponse, content_type = "application/json"),
+            (status = 503, description = "Too many orders are waiting for a machine", body = ErrorResponse, content_type = "application/json",
+                headers(("Retry-After" = u64, description = "Seconds to wait before trying again"))),
+            (status = 504, description = "The payment provider did not answer in time", body = ErrorResponse, content_type = "application/json")
     )
 )]
 async fn post_order(
-    // Inject shared RabbitMQ producer
-    Extension(producer): Extension<SharedProducer>,
+    // Caller placing the order, a customer or a barista at the counter
+    principal: auth::Principal,
+    // Address of the client, for the rate limit per IP
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    // Inject the limits, producer, metrics, orders, estimator, menu and payments
+    Extension(desk): Extension<OrderDesk>,
     // Deserialize JSON payload into OrderRequest
     Json(payload): Json<OrderRequest>,
-) -> Result<(StatusCode, Json<OrderResponse>), (StatusCode, Json<ErrorResponse>)> {
-    // 1) Validate the requested drink type
-    if !matches!(
-        payload.drink_type.as_str(),
-        "espresso" | "coffee" | "cappuccino"
-    ) {
+) -> Result<(StatusCode, Json<OrderResponse>), Response> {
+    principal
+        .require(&[auth::Role::Customer, auth::Role::Barista])
+        .map_err(IntoResponse::into_response)?;
+    let ip = desk.limits.client_ip(peer, &headers);
+    let customer = principal.subject.clone();
+    place_order(desk, &principal.subject, customer, ip, payload).await
+}
+
+/// Validates, prices, charges and publishes an order placed by `caller` from `ip` and remembers it
+/// as an order of `customer`; shared by POST /order and re-ordering a customer's usual
+async fn place_order(
+    desk: OrderDesk,
+    caller: &str,
+    customer: String,
+    ip: IpAddr,
+    payload: OrderRequest,
+) -> Result<(StatusCode, Json<OrderResponse>), Response> {
+    let OrderDesk {
+        limits,
+        producer,
+        metrics,
+        orders,
+        load,
+        estimator,
+        menu,
+        payments,
+    } = desk;
+
+    // 1) Validate the requested drink type against the menu
+    if !menu.serves(&payload.drink_type) {
+        // Unknown types share one label to keep the metric's cardinality bounded
+        metrics
+            .orders_rejected
+            .with_label_values(&["unknown", "invalid_type"])
+            .inc();
         let err = ErrorResponse {
             error: "This is a coffee-only establishment ☕".into(),
         };
         // Return 400 Bad Request for unsupported drink types
-        return Err((StatusCode::BAD_REQUEST, Json(err)));
+        return Err((StatusCode::BAD_REQUEST, Json(err)).into_response());
     }
 
-    // 2) Construct the order message with a new UUID and current timestamp
+    // Price the drink with its size and extras, refusing anything that is not on the menu
     let order_id = Uuid::new_v4().to_string();
+    let priced = menu.price(
+        &order_id,
+        &payload.drink_type,
+        payload.size.as_deref(),
+        &payload.extras,
+        chrono::Utc::now(),
+    );
+    let receipt = match priced {
+        Ok(receipt) => receipt,
+        Err(error) => {
+            metrics
+                .orders_rejected
+                .with_label_values(&[payload.drink_type.as_str(), "invalid_item"])
+                .inc();
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error })).into_response());
+        }
+    };
+
+    // Turn the order away with 429 or 503 and a Retry-After if the caller or the shop is too busy
+    let limited = Some(caller).filter(|caller| *caller != auth::ANONYMOUS);
+    if let Err(throttled) = limits.check(ip, limited) {
+        let reason = match throttled {
+            limits::Throttled::RateLimited(_) => "rate_limited",
+            limits::Throttled::QueueFull(_) => "queue_full",
+        };
+        warn!(%ip, subject = %caller, reason, "Order throttled");
+        metrics
+            .orders_rejected
+            .with_label_values(&[payload.drink_type.as_str(), reason])
+            .inc();
+        return Err(throttled.into_response());
+    }
+
+    // Only paid orders are published
+    let payment = match payments.charge(&receipt).await {
+        Ok(payment) => payment,
+        Err(e) => {
+            let reason = match e {
+                payments::PaymentError::Declined(_) => "payment_declined",
+                _ => "payment_failed",
+            };
+            warn!(%order_id, error=%e, "Order not paid");
+            metrics
+                .orders_rejected
+                .with_label_values(&[payload.drink_type.as_str(), reason])
+                .inc();
+            // The provider may still complete a charge it did not confirm, so it is voided
+            if let payments::PaymentError::Timeout(_) = e {
+                let outcome = match payments.void(&order_id).await {
+                    Ok(()) => "voided",
+                    Err(e) => {
+                        error!(%order_id, error=%e, "Void of unconfirmed charge failed, reconcile it with the provider");
+                        "failed"
+                    }
+                };
+                metrics.voids.with_label_values(&[outcome]).inc();
+            }
+            return Err(e.into_response());
+        }
+    };
+
+    // 2) Remember the order before publishing it, a machine with an idle unit may report its
+    // progress right away; it waits behind the queue and the orders being prepared
+    let now = chrono::Utc::now();
+    let drink_type = payload.drink_type.clone();
+    let (estimated_ready_at, total_cents, currency) = {
+        let mut orders = orders.lock().unwrap();
+        let record = orders::OrderRecord {
+            order_id: order_id.clone(),
+            drink_type: payload.drink_type,
+            size: payload.size,
+            extras: payload.extras,
+            customer,
+            placed_at: now,
+            ahead: load
+                .queue_depth()
+                .saturating_add(u32::try_from(load.busy_units()).unwrap_or(u32::MAX)),
+            started_before: orders.started(),
+            state: orders::OrderState::Queued,
+            stage: None,
+            started_at: None,
+            finished_at: None,
+            receipt,
+            payment: orders::PaymentState::Paid,
+        };
+        let estimated_ready_at =
+            estimator.queued(&record.drink_type, record.ahead, load.active_units(), now);
+        let total_cents = record.receipt.total_cents;
+        let currency = record.receipt.currency.clone();
+        orders.insert(record);
+        (estimated_ready_at, total_cents, currency)
+    };
+
+    // 3) Construct the order message with the new UUID, current timestamp and payment
     let order_msg = rabbitmq::OrderMessage {
         order_id: order_id.clone(),
-        r#type: payload.drink_type.clone(),
+        r#type: drink_type.clone(),
         timestamp: chrono::Utc::now(),
+        payment: payment.clone(),
     };
 
-    // Acquire lock on the producer and attempt to publish the message
-    let prod = producer.lock().await;
-    if let Err(e) = prod.publish(order_msg).await {
+    // Acquire lock on the producer and attempt to publish the message; the lock is released
+    // before a refund, which may wait for the payment provider
+    let published = {
+        let prod = producer.lock().await;
+        let started = Instant::now();
+        let published = prod.publish(order_msg).await;
+        metrics
+            .publish_latency
+            .observe(started.elapsed().as_secs_f64());
+        published
+    };
+    if let Err(e) = published {
         error!("Publish failed: {e}");
+        // The order will never be brewed, forget it again
+        orders.lock().unwrap().remove(&order_id);
+        // The customer gets their money back right away
+        let outcome = match payments.refund(&payment).await {
+            Ok(()) => "refunded",
+            Err(e) => {
+                error!(%order_id, payment_id = %payment.id, error=%e, "Refund of unpublished order failed");
+                "failed"
+            }
+        };
+        metrics.refunds.with_label_values(&[outcome]).inc();
+        metrics
+            .orders_rejected
+            .with_label_values(&[drink_type.as_str(), "publish_failed"])
+            .inc();
         let err = ErrorResponse {
             error: "Internal server error".into(),
         };
         // Return 500 Internal Server Error if publish fails
-        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(err)));
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(err)).into_response());
     }
 
-    // 3) On success, respond with 202 Accepted and the generated order ID
+    metrics
+        .orders_accepted
+        .with_label_values(&[drink_type.as_str()])
+        .inc();
+
+    // 4) On success, respond with 202 Accepted, the generated order ID, the estimate and the total
     let resp = OrderResponse {
         message: "Order received".into(),
         order_id,
+        estimated_ready_at,
+        total_cents,
+        currency,
     };
     Ok((StatusCode::ACCEPTED, Json(resp)))
 }
@@ -168,13 +577,18 @@ async fn post_order(
     tag = "Orders",
     responses(
         (status = 200, description = "Current queue length", body = rabbitmq::QueueLength, content_type = "application/json"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
         (status = 500, description = "Internal server error", body = ErrorResponse, content_type = "application/json")
     )
 )]
-async fn get_queue_length()
--> Result<(StatusCode, Json<rabbitmq::QueueLength>), (StatusCode, Json<ErrorResponse>)> {
+async fn get_queue_length(
+    // Any authenticated caller may see how busy the shop is
+    _principal: auth::Principal,
+    // Inject the configuration for the management API address and credentials
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<(StatusCode, Json<rabbitmq::QueueLength>), (StatusCode, Json<ErrorResponse>)> {
     // Attempt to fetch queue length via RabbitMQ management API or passive inspection
-    match rabbitmq::fetch_queue_length().await {
+    match rabbitmq::fetch_queue_length(&config).await {
         Ok(len) => Ok((
             StatusCode::OK,
             Json(rabbitmq::QueueLength {
@@ -0,0 +1,179 @@
+use crate::config::MenuConfig;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use utoipa::ToSchema;
+
+/// Width of a plain-text receipt in characters
+const RECEIPT_WIDTH: usize = 40;
+
+/// Extras a single drink may have, so one order cannot grow its receipt without bound
+pub const MAX_EXTRAS: usize = 10;
+
+/// Prices drinks from the configured menu; amounts are in cents of the menu's currency
+pub struct Menu {
+    currency: String,              // ISO 4217 code, e.g. EUR
+    drinks: BTreeMap<String, u64>, // Base price by beverage type
+    sizes: BTreeMap<String, u64>,  // Surcharge by cup size
+    default_size: String,          // Size of orders that do not name one
+    extras: BTreeMap<String, u64>, // Surcharge by extra
+    taxes: BTreeMap<String, f64>,  // Tax rate in percent by name, applied to the subtotal
+}
+
+/// A line of a receipt
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReceiptLine {
+    pub description: String, // Drink, size or extra
+    pub amount_cents: u64,   // Price of the line
+}
+
+/// A tax charged on a receipt's subtotal
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TaxLine {
+    pub name: String,      // Name of the tax, e.g. vat
+    pub rate_percent: f64, // Rate applied to the subtotal
+    pub amount_cents: u64, // Tax amount, rounded to the cent
+}
+
+/// Itemized price of an order
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Receipt {
+    pub order_id: String,
+    pub issued_at: DateTime<Utc>,
+    pub currency: String,
+    pub lines: Vec<ReceiptLine>, // Drink, then size and extras with a surcharge
+    pub subtotal_cents: u64,     // Sum of the lines
+    pub taxes: Vec<TaxLine>,     // Taxes on the subtotal
+    pub total_cents: u64,        // Subtotal plus taxes
+}
+
+impl Menu {
+    pub fn new(config: &MenuConfig) -> Self {
+        Self {
+            currency: config.currency.clone(),
+            drinks: config.drinks.clone(),
+            sizes: config.sizes.clone(),
+            default_size: config.default_size.clone(),
+            extras: config.extras.clone(),
+            taxes: config.taxes.clone(),
+        }
+    }
+
+    /// Is the beverage type on the menu?
+    pub fn serves(&self, drink_type: &str) -> bool {
+        self.drinks.contains_key(drink_type)
+    }
+
+    /// Prices a drink with its size and extras, failing on anything that is not on the menu
+    pub fn price(
+        &self,
+        order_id: &str,
+        drink_type: &str,
+        size: Option<&str>,
+        extras: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<Receipt, String> {
+        if extras.len() > MAX_EXTRAS {
+            return Err(format!("At most {} extras per drink", MAX_EXTRAS));
+        }
+        // Prices come from the configuration, sums beyond a u64 are refused rather than wrapped
+        let too_expensive = || "The price of the order is out of range".to_string();
+
+        let base = *self
+            .drinks
+            .get(drink_type)
+            .ok_or_else(|| format!("{} is not on the menu", drink_type))?;
+        let size = size.unwrap_or(&self.default_size);
+        let size_surcharge = *self
+            .sizes
+            .get(size)
+            .ok_or_else(|| format!("Unknown size {}", size))?;
+
+        let mut lines = vec![ReceiptLine {
+            description: format!("{} ({})", title(drink_type), size),
+            amount_cents: base.checked_add(size_surcharge).ok_or_else(too_expensive)?,
+        }];
+        for extra in extras {
+            let surcharge = *self
+                .extras
+                .get(extra)
+                .ok_or_else(|| format!("Unknown extra {}", extra))?;
+            lines.push(ReceiptLine {
+                description: format!("+ {}", extra.replace('_', " ")),
+                amount_cents: surcharge,
+            });
+        }
+
+        let subtotal_cents = lines
+            .iter()
+            .try_fold(0u64, |sum, line| sum.checked_add(line.amount_cents))
+            .ok_or_else(too_expensive)?;
+        let taxes: Vec<TaxLine> = self
+            .taxes
+            .iter()
+            .map(|(name, rate)| TaxLine {
+                name: name.clone(),
+                rate_percent: *rate,
+                amount_cents: (subtotal_cents as f64 * rate / 100.0).round() as u64,
+            })
+            .collect();
+        let total_cents = taxes
+            .iter()
+            .try_fold(subtotal_cents, |sum, tax| sum.checked_add(tax.amount_cents))
+            .ok_or_else(too_expensive)?;
+
+        Ok(Receipt {
+            order_id: order_id.into(),
+            issued_at: now,
+            currency: self.currency.clone(),
+            lines,
+            subtotal_cents,
+            taxes,
+            total_cents,
+        })
+    }
+}
+
+impl Receipt {
+    /// Renders the receipt for a till printer or a terminal
+    pub fn to_text(&self) -> String {
+        let rule = "-".repeat(RECEIPT_WIDTH);
+        let mut text = String::new();
+        let _ = writeln!(text, "Order {}", self.order_id);
+        let _ = writeln!(text, "{}", self.issued_at.format("%Y-%m-%d %H:%M UTC"));
+        let _ = writeln!(text, "{}", rule);
+        for line in &self.lines {
+            let _ = writeln!(text, "{}", row(&line.description, line.amount_cents));
+        }
+        let _ = writeln!(text, "{}", rule);
+        let _ = writeln!(text, "{}", row("Subtotal", self.subtotal_cents));
+        for tax in &self.taxes {
+            let label = format!("{} {}%", tax.name.to_uppercase(), tax.rate_percent);
+            let _ = writeln!(text, "{}", row(&label, tax.amount_cents));
+        }
+        let _ = writeln!(text, "{}", rule);
+        let _ = writeln!(
+            text,
+            "{}",
+            row(&format!("Total {}", self.currency), self.total_cents)
+        );
+        text
+    }
+}
+
+/// A label on the left and an amount on the right of a receipt line
+fn row(label: &str, cents: u64) -> String {
+    let amount = format!("{}.{:02}", cents / 100, cents % 100);
+    let width = RECEIPT_WIDTH.saturating_sub(amount.len() + 1);
+    format!("{:<width$} {}", label, amount, width = width)
+}
+
+/// Capitalizes a beverage type, e.g. espresso becomes Espresso
+fn title(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
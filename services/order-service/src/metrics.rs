@@ -0,0 +1,80 @@
+use axum::{extract::Extension, http::header, response::IntoResponse};
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounterVec, Opts, Registry, TextEncoder};
+use std::sync::Arc;
+
+/// Prometheus metrics of the order service
+pub struct Metrics {
+    registry: Registry,                 // Registry all metrics below belong to
+    pub orders_accepted: IntCounterVec, // Orders published to the queue, by beverage type
+    pub orders_rejected: IntCounterVec, // Orders turned away, by beverage type and reason
+    pub publish_latency: Histogram,     // Time until RabbitMQ confirmed a published order
+    pub refunds: IntCounterVec,         // Refunds of failed or unpublished orders, by outcome
+    pub voids: IntCounterVec,           // Voids of charges that timed out, by outcome
+}
+
+impl Metrics {
+    /// Creates and registers all metrics
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+        let orders_accepted = IntCounterVec::new(
+            Opts::new("orders_accepted_total", "Orders accepted and published"),
+            &["type"],
+        )?;
+        let orders_rejected = IntCounterVec::new(
+            Opts::new(
+                "orders_rejected_total",
+                "Orders rejected before or while publishing",
+            ),
+            &["type", "reason"],
+        )?;
+        let publish_latency = Histogram::with_opts(HistogramOpts::new(
+            "order_publish_duration_seconds",
+            "Time to publish an order and receive the broker confirmation",
+        ))?;
+        let refunds = IntCounterVec::new(
+            Opts::new("order_refunds_total", "Refunds of paid orders"),
+            &["outcome"],
+        )?;
+        let voids = IntCounterVec::new(
+            Opts::new(
+                "order_payment_voids_total",
+                "Voids of charges the provider did not confirm in time",
+            ),
+            &["outcome"],
+        )?;
+        registry.register(Box::new(orders_accepted.clone()))?;
+        registry.register(Box::new(orders_rejected.clone()))?;
+        registry.register(Box::new(publish_latency.clone()))?;
+        registry.register(Box::new(refunds.clone()))?;
+        registry.register(Box::new(voids.clone()))?;
+
+        Ok(Self {
+            registry,
+            orders_accepted,
+            orders_rejected,
+            publish_latency,
+            refunds,
+            voids,
+        })
+    }
+
+    /// Encodes all metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            tracing::error!(error=%e, "Failed to encode metrics");
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Handler for GET /metrics, scraped by Prometheus
+pub async fn get_metrics(Extension(metrics): Extension<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(
+            header::CONTENT_TYPE,
+            TextEncoder::new().format_type().to_string(),
+        )],
+        metrics.render(),
+    )
+}
@@ -0,0 +1,292 @@
+use crate::ErrorResponse;
+use crate::eta::Estimator;
+use crate::load::SharedLoad;
+use crate::menu::Receipt;
+use crate::progress::OrderProgress;
+use auth::{AuthError, Principal, Role};
+use axum::{
+    Json,
+    extract::{Extension, Path},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Number of recent orders that are remembered
+const ORDERS_CAPACITY: usize = 10_000;
+
+/// Where an order is on its way to the counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderState {
+    Queued,     // Waiting for a brewing unit
+    InProgress, // Being prepared
+    Done,       // Ready at the counter
+    Failed,     // Could not be prepared
+}
+
+/// Whether the customer has been charged for an order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentState {
+    Paid,     // Charged before the order was published
+    Refunded, // Paid back after the order failed
+}
+
+/// An order placed through this service
+#[derive(Debug, Clone)]
+pub struct OrderRecord {
+    pub order_id: String,                   // Id the order was published with
+    pub drink_type: String,                 // Beverage type: espresso, coffee, cappuccino
+    pub size: Option<String>,               // Cup size as ordered, none for the default size
+    pub extras: Vec<String>,                // Extras as ordered
+    pub customer: String,                   // Subject of the caller who placed it
+    pub placed_at: DateTime<Utc>,           // When the order was placed
+    pub ahead: u32,                         // Orders ahead of it when it was placed
+    pub started_before: u64,                // Orders started before it was placed
+    pub state: OrderState,                  // Derived from the progress updates
+    pub stage: Option<String>,              // Latest progress stage, none while queued
+    pub started_at: Option<DateTime<Utc>>,  // When the machine started preparing it
+    pub finished_at: Option<DateTime<Utc>>, // When it was done or failed
+    pub receipt: Receipt,                   // What the customer was charged
+    pub payment: PaymentState,              // Paid, or refunded once the order failed
+}
+
+/// The most recent orders and how far they have got
+#[derive(Default)]
+pub struct Orders {
+    records: HashMap<String, OrderRecord>, // Orders by id
+    placed: VecDeque<String>,              // Order ids, oldest first
+    started: u64,                          // Orders the machine has started, of any service
+}
+
+impl Orders {
+    /// Orders the machine has started so far
+    pub fn started(&self) -> u64 {
+        self.started
+    }
+
+    /// Remembers an order, forgetting the oldest one once full
+    pub fn insert(&mut self, record: OrderRecord) {
+        if self.placed.len() == ORDERS_CAPACITY {
+            let oldest = self.placed.pop_front().unwrap_or_default();
+            self.records.remove(&oldest);
+        }
+        self.placed.push_back(record.order_id.clone());
+        self.records.insert(record.order_id.clone(), record);
+    }
+
+    /// Forgets an order again, one that could not be published
+    pub fn remove(&mut self, order_id: &str) {
+        if self.records.remove(order_id).is_some() {
+            self.placed.retain(|id| id != order_id);
+        }
+    }
+
+    /// Looks up a remembered order
+    pub fn get(&self, order_id: &str) -> Option<&OrderRecord> {
+        self.records.get(order_id)
+    }
+
+    /// Remembered orders of a customer, newest first
+    pub fn of_customer<'a>(&'a self, customer: &'a str) -> impl Iterator<Item = &'a OrderRecord> {
+        self.placed
+            .iter()
+            .rev()
+            .filter_map(|id| self.records.get(id))
+            .filter(move |record| record.customer == customer)
+    }
+
+    /// Did the customer place the order?
+    pub fn is_owner(&self, order_id: &str, customer: &str) -> bool {
+        self.records
+            .get(order_id)
+            .is_some_and(|record| record.customer == customer)
+    }
+
+    /// Records that the payment of an order was refunded
+    pub fn refunded(&mut self, order_id: &str) {
+        match self.records.get_mut(order_id) {
+            Some(record) => record.payment = PaymentState::Refunded,
+            None => warn!(%order_id, "Refunded order is unknown or already forgotten"),
+        }
+    }
+
+    /// Records a progress update of the machine; every order starts with grinding, so those
+    /// updates also count the orders that have left the queue
+    pub fn apply(&mut self, update: &OrderProgress) {
+        if update.stage == "grinding" {
+            self.started += 1;
+        }
+        let Some(record) = self.records.get_mut(&update.order_id) else {
+            return;
+        };
+        record.stage = Some(update.stage.clone());
+        match update.stage.as_str() {
+            "done" => {
+                record.state = OrderState::Done;
+                record.finished_at = Some(update.at);
+            }
+            "failed" => {
+                record.state = OrderState::Failed;
+                record.finished_at = Some(update.at);
+            }
+            _ => {
+                record.state = OrderState::InProgress;
+                record.started_at.get_or_insert(update.at);
+            }
+        }
+    }
+
+    /// Orders still ahead of a queued order, those started since it was placed have left the queue
+    pub fn ahead_of(&self, record: &OrderRecord) -> u32 {
+        let left = self.started.saturating_sub(record.started_before);
+        record
+            .ahead
+            .saturating_sub(u32::try_from(left).unwrap_or(u32::MAX))
+    }
+
+    /// Estimated ready time of an order, none once it has failed or if it is out of range
+    pub fn estimate(
+        &self,
+        record: &OrderRecord,
+        estimator: &Estimator,
+        units: usize,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match record.state {
+            OrderState::Queued => {
+                estimator.queued(&record.drink_type, self.ahead_of(record), units, now)
+            }
+            OrderState::InProgress => {
+                let started_at = record.started_at.unwrap_or(now);
+                estimator.in_progress(&record.drink_type, started_at, now)
+            }
+            OrderState::Done => record.finished_at,
+            OrderState::Failed => None,
+        }
+    }
+}
+
+/// Orders shared between placing, tracking and streaming them
+pub type SharedOrders = Arc<Mutex<Orders>>;
+
+/// Status of an order returned by GET /order/{id}
+#[derive(Serialize, ToSchema)]
+pub struct OrderStatusResponse {
+    pub order_id: String,
+    #[serde(rename = "type")]
+    pub drink_type: String,
+    pub state: OrderState,         // Queued, in progress, done or failed
+    pub stage: Option<String>,     // Null while the order is queued
+    pub placed_at: DateTime<Utc>,  // When the order was placed
+    pub orders_ahead: Option<u32>, // Null once the order has started
+    pub payment: PaymentState,     // Refunded once the order has failed
+    pub estimated_ready_at: Option<DateTime<Utc>>, // Null once failed or if out of range
+}
+
+/// Handler for GET /order/{id}, refreshing the estimated ready time
+#[utoipa::path(
+    get,
+    path = "/order/{id}",
+    tag = "Orders",
+    params(("id" = String, Path, description = "Order id")),
+    responses(
+        (status = 200, description = "Status and estimated ready time of the order", body = OrderStatusResponse, content_type = "application/json"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
+        (status = 403, description = "Customers may only see their own orders", body = ErrorResponse, content_type = "application/json"),
+        (status = 404, description = "Unknown or forgotten order", body = ErrorResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_order(
+    // Caller, baristas see every order and customers their own
+    principal: Principal,
+    // Inject the orders to look the order up in
+    Extension(orders): Extension<SharedOrders>,
+    // Inject the shop load and the estimator for the ready time
+    Extension(load): Extension<SharedLoad>,
+    Extension(estimator): Extension<Arc<Estimator>>,
+    Path(id): Path<String>,
+) -> Result<Json<OrderStatusResponse>, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Customer, Role::Barista])?;
+
+    let orders = orders.lock().unwrap();
+    let record = visible_order(&orders, &id, &principal)?;
+
+    let now = Utc::now();
+    Ok(Json(OrderStatusResponse {
+        order_id: record.order_id.clone(),
+        drink_type: record.drink_type.clone(),
+        state: record.state,
+        stage: record.stage.clone(),
+        placed_at: record.placed_at,
+        orders_ahead: (record.state == OrderState::Queued).then(|| orders.ahead_of(record)),
+        payment: record.payment,
+        estimated_ready_at: orders.estimate(record, &estimator, load.active_units(), now),
+    }))
+}
+
+/// Handler for GET /order/{id}/receipt, as JSON or as plain text if the client accepts only that
+#[utoipa::path(
+    get,
+    path = "/order/{id}/receipt",
+    tag = "Orders",
+    params(("id" = String, Path, description = "Order id")),
+    responses(
+        (status = 200, description = "Itemized receipt of the order", content(
+            (Receipt = "application/json"),
+            (String = "text/plain")
+        )),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
+        (status = 403, description = "Customers may only see their own receipts", body = ErrorResponse, content_type = "application/json"),
+        (status = 404, description = "Unknown or forgotten order", body = ErrorResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_receipt(
+    // Caller, baristas see every receipt and customers their own
+    principal: Principal,
+    // Inject the orders to look the receipt up in
+    Extension(orders): Extension<SharedOrders>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    principal.require(&[Role::Customer, Role::Barista])?;
+
+    let receipt = {
+        let orders = orders.lock().unwrap();
+        visible_order(&orders, &id, &principal)?.receipt.clone()
+    };
+    let wants_text = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/plain") && !accept.contains("json"));
+    if wants_text {
+        let content_type = [(header::CONTENT_TYPE, "text/plain; charset=utf-8")];
+        Ok((content_type, receipt.to_text()).into_response())
+    } else {
+        Ok(Json(receipt).into_response())
+    }
+}
+
+/// Looks up an order the caller may see: baristas see every order and customers their own
+fn visible_order<'a>(
+    orders: &'a Orders,
+    id: &str,
+    principal: &Principal,
+) -> Result<&'a OrderRecord, (StatusCode, Json<ErrorResponse>)> {
+    let Some(record) = orders.get(id) else {
+        let err = ErrorResponse {
+            error: format!("Order {} not found", id),
+        };
+        return Err((StatusCode::NOT_FOUND, Json(err)));
+    };
+    if !principal.has_any(&[Role::Barista]) && record.customer != principal.subject {
+        return Err(AuthError::Forbidden.into());
+    }
+    Ok(record)
+}
@@ -0,0 +1,198 @@
+use crate::ErrorResponse;
+use crate::config::{FakeOutcome, FakePaymentConfig, PaymentsConfig};
+use crate::menu::Receipt;
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Why an order could not be paid for or refunded
+#[derive(Debug)]
+pub enum PaymentError {
+    Declined(String),    // The provider refused the payment
+    Timeout(Duration),   // The provider did not answer in time
+    Unavailable(String), // The provider could not be reached or failed
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentError::Declined(reason) => write!(f, "Payment declined: {}", reason),
+            PaymentError::Timeout(timeout) => {
+                write!(f, "Payment provider did not answer within {:?}", timeout)
+            }
+            PaymentError::Unavailable(reason) => write!(f, "Payment provider failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for PaymentError {}
+
+impl IntoResponse for PaymentError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            PaymentError::Declined(_) => StatusCode::PAYMENT_REQUIRED,
+            PaymentError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            PaymentError::Unavailable(_) => StatusCode::BAD_GATEWAY,
+        };
+        let err = ErrorResponse {
+            error: self.to_string(),
+        };
+        (status, Json(err)).into_response()
+    }
+}
+
+/// A settled payment; it travels with the order message so a failed order can be refunded
+/// from the dead-lettered message alone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Payment {
+    pub id: String,        // Id the provider knows the payment by
+    pub amount_cents: u64, // Amount charged
+    pub currency: String,  // ISO 4217 code of the amount
+}
+
+/// A payment service provider; implementations only talk to the provider, timeouts are applied
+/// by `Payments`
+pub trait PaymentProvider: Send + Sync {
+    /// Charges the customer for an order and returns the id of the payment; the order id is the
+    /// idempotency key of the charge
+    fn charge<'a>(
+        &'a self,
+        order_id: &'a str,
+        amount_cents: u64,
+        currency: &'a str,
+    ) -> BoxFuture<'a, Result<String, PaymentError>>;
+
+    /// Pays a settled payment back in full
+    fn refund<'a>(&'a self, payment: &'a Payment) -> BoxFuture<'a, Result<(), PaymentError>>;
+
+    /// Cancels or pays back the charge made for an order, if the provider completed one; used when
+    /// the charge was not answered in time
+    fn void<'a>(&'a self, order_id: &'a str) -> BoxFuture<'a, Result<(), PaymentError>>;
+}
+
+/// In-process provider for development and tests: after a delay it approves, declines, fails or
+/// never answers, as configured
+pub struct FakeProvider {
+    outcome: FakeOutcome, // What every charge and refund ends with
+    latency: Duration,    // Delay before answering
+}
+
+impl FakeProvider {
+    pub fn new(config: &FakePaymentConfig) -> Self {
+        Self {
+            outcome: config.outcome,
+            latency: Duration::from_millis(config.latency_ms),
+        }
+    }
+}
+
+impl PaymentProvider for FakeProvider {
+    fn charge<'a>(
+        &'a self,
+        order_id: &'a str,
+        amount_cents: u64,
+        currency: &'a str,
+    ) -> BoxFuture<'a, Result<String, PaymentError>> {
+        Box::pin(async move {
+            tokio::time::sleep(self.latency).await;
+            match self.outcome {
+                FakeOutcome::Approve => {
+                    let id = format!("fake-{}", Uuid::new_v4());
+                    tracing::info!(order_id, amount_cents, currency, payment_id = %id, "Fake payment approved");
+                    Ok(id)
+                }
+                FakeOutcome::Decline => Err(PaymentError::Declined(
+                    "The fake provider declines every payment".into(),
+                )),
+                FakeOutcome::Error => Err(PaymentError::Unavailable(
+                    "The fake provider is configured to fail".into(),
+                )),
+                FakeOutcome::Timeout => std::future::pending().await,
+            }
+        })
+    }
+
+    fn refund<'a>(&'a self, payment: &'a Payment) -> BoxFuture<'a, Result<(), PaymentError>> {
+        Box::pin(async move {
+            tokio::time::sleep(self.latency).await;
+            match self.outcome {
+                // Only approved payments are ever refunded, so a declining provider refunds too
+                FakeOutcome::Approve | FakeOutcome::Decline => {
+                    tracing::info!(payment_id = %payment.id, "Fake payment refunded");
+                    Ok(())
+                }
+                FakeOutcome::Error => Err(PaymentError::Unavailable(
+                    "The fake provider is configured to fail".into(),
+                )),
+                FakeOutcome::Timeout => std::future::pending().await,
+            }
+        })
+    }
+
+    fn void<'a>(&'a self, order_id: &'a str) -> BoxFuture<'a, Result<(), PaymentError>> {
+        Box::pin(async move {
+            tokio::time::sleep(self.latency).await;
+            match self.outcome {
+                FakeOutcome::Approve | FakeOutcome::Decline => {
+                    tracing::info!(order_id, "Fake charge voided");
+                    Ok(())
+                }
+                FakeOutcome::Error => Err(PaymentError::Unavailable(
+                    "The fake provider is configured to fail".into(),
+                )),
+                FakeOutcome::Timeout => std::future::pending().await,
+            }
+        })
+    }
+}
+
+/// Charges and refunds orders through the configured provider, giving up after the timeout
+pub struct Payments {
+    provider: Box<dyn PaymentProvider>, // The fake one, until a real provider is added
+    timeout: Duration,                  // Longest wait for the provider per call
+}
+
+impl Payments {
+    pub fn new(config: &PaymentsConfig) -> Self {
+        Self {
+            provider: Box::new(FakeProvider::new(&config.fake)),
+            timeout: Duration::from_secs(config.timeout_secs),
+        }
+    }
+
+    /// Charges the total of an order's receipt
+    pub async fn charge(&self, receipt: &Receipt) -> Result<Payment, PaymentError> {
+        let charge =
+            self.provider
+                .charge(&receipt.order_id, receipt.total_cents, &receipt.currency);
+        let id = tokio::time::timeout(self.timeout, charge)
+            .await
+            .map_err(|_| PaymentError::Timeout(self.timeout))??;
+        Ok(Payment {
+            id,
+            amount_cents: receipt.total_cents,
+            currency: receipt.currency.clone(),
+        })
+    }
+
+    /// Refunds a payment in full
+    pub async fn refund(&self, payment: &Payment) -> Result<(), PaymentError> {
+        tokio::time::timeout(self.timeout, self.provider.refund(payment))
+            .await
+            .map_err(|_| PaymentError::Timeout(self.timeout))?
+    }
+
+    /// Voids whatever the provider charged for an order whose charge timed out
+    pub async fn void(&self, order_id: &str) -> Result<(), PaymentError> {
+        tokio::time::timeout(self.timeout, self.provider.void(order_id))
+            .await
+            .map_err(|_| PaymentError::Timeout(self.timeout))?
+    }
+}
@@ -0,0 +1,112 @@
+use crate::ErrorResponse;
+use crate::orders::SharedOrders;
+use auth::{AuthError, Principal, Role};
+use axum::{
+    Json,
+    extract::{Extension, Query},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use utoipa::{IntoParams, ToSchema};
+
+/// Number of progress updates buffered for slow subscribers
+pub const PROGRESS_BUFFER: usize = 256;
+
+/// Sender every stream subscriber receives progress updates from
+pub type ProgressSender = broadcast::Sender<OrderProgress>;
+
+/// Progress of a single order as reported by the coffee machine
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct OrderProgress {
+    pub order_id: String,
+    pub unit: usize,   // Brewing unit preparing the order
+    pub stage: String, // grinding, brewing, frothing, dispensing, done or failed
+    pub at: chrono::DateTime<chrono::Utc>,
+}
+
+impl OrderProgress {
+    /// Is this the last update of the order?
+    pub fn is_final(&self) -> bool {
+        matches!(self.stage.as_str(), "done" | "failed")
+    }
+}
+
+/// Query parameters for GET /orders/stream
+#[derive(Deserialize, IntoParams)]
+pub struct ProgressQuery {
+    /// Only stream the progress of this order; the stream ends once it is done or failed
+    pub order_id: Option<String>,
+}
+
+/// Handler for streaming order progress as Server-Sent Events
+#[utoipa::path(
+    get,
+    path = "/orders/stream",
+    tag = "Orders",
+    params(ProgressQuery),
+    responses(
+        (status = 200, description = "Stream of order progress updates", body = OrderProgress, content_type = "text/event-stream"),
+        (status = 401, description = "Missing or invalid credentials", body = ErrorResponse, content_type = "application/json"),
+        (status = 403, description = "Customers may only follow their own orders", body = ErrorResponse, content_type = "application/json")
+    )
+)]
+pub async fn get_order_stream(
+    // Caller, baristas follow every order and customers their own
+    principal: Principal,
+    // Inject the progress sender to subscribe to
+    Extension(progress): Extension<ProgressSender>,
+    // Inject the orders to check customers against
+    Extension(orders): Extension<SharedOrders>,
+    // Ends the stream on shutdown
+    Extension(shutdown): Extension<CancellationToken>,
+    Query(query): Query<ProgressQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, (StatusCode, Json<ErrorResponse>)>
+{
+    principal.require(&[Role::Customer, Role::Barista])?;
+    if !principal.has_any(&[Role::Barista]) {
+        let own_order = query
+            .order_id
+            .as_ref()
+            .is_some_and(|id| orders.lock().unwrap().is_owner(id, &principal.subject));
+        if !own_order {
+            return Err(AuthError::Forbidden.into());
+        }
+    }
+
+    let rx = progress.subscribe();
+    let stream = futures_util::stream::unfold(Some(rx), move |rx| {
+        let order_id = query.order_id.clone();
+        async move {
+            // The receiver is dropped once the requested order has finished
+            let mut rx = rx?;
+            loop {
+                match rx.recv().await {
+                    Ok(update) => {
+                        if order_id.as_ref().is_some_and(|id| *id != update.order_id) {
+                            continue;
+                        }
+                        let next = if order_id.is_some() && update.is_final() {
+                            None
+                        } else {
+                            Some(rx)
+                        };
+                        let event = Event::default().event("progress").json_data(&update);
+                        return Some((event, next));
+                    }
+                    Err(RecvError::Lagged(missed)) => {
+                        tracing::warn!(
+                            "Progress subscriber lagged behind, {} updates skipped",
+                            missed
+                        );
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+    Ok(Sse::new(stream.take_until(shutdown.cancelled_owned())).keep_alive(KeepAlive::default()))
+}
@@ -1,14 +1,30 @@
+use crate::config::{self, AmqpConfig, Config};
+use crate::metrics::Metrics;
+use crate::orders::SharedOrders;
+use crate::payments::{Payment, Payments};
+use crate::progress::{OrderProgress, ProgressSender};
 use anyhow::Result;
+use futures_util::StreamExt;
 use lapin::{
     BasicProperties, Channel, Connection, ConnectionProperties,
-    options::{BasicPublishOptions, QueueDeclareOptions},
-    types::FieldTable,
+    options::{
+        BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicPublishOptions,
+        QueueDeclareOptions,
+    },
+    types::{AMQPValue, FieldTable},
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 use utoipa::{IntoParams, ToSchema};
 
+/// Delay before a failed order whose refund failed is retried
+const REFUND_RETRY_DELAY: Duration = Duration::from_secs(5);
+
 /// Producer encapsulates a RabbitMQ Queue producer instance using lapin
 pub struct Producer {
+    connection: Connection,
     channel: Channel,
     queue_name: String,
 }
@@ -20,29 +36,40 @@ pub struct OrderMessage {
     #[serde(rename = "type")]
     pub r#type: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    #[param(value_type = Object)]
+    pub payment: Payment, // Refunded if the order ends up in 'order.failed'
+}
+
+/// The fields of a dead-lettered order needed to refund it
+#[derive(Deserialize)]
+struct FailedOrder {
+    order_id: String,
+    payment: Option<Payment>, // Missing on orders placed before payment was required
 }
 
 impl Producer {
     /// Initialize the AMQP connection, open a channel, and declare the queue
-    pub async fn init() -> Result<Self> {
-        let host = std::env::var("RABBITMQ_HOST").unwrap_or_else(|_| "localhost".into());
-        let port: u16 = std::env::var("RABBITMQ_PORT")
-            .unwrap_or_else(|_| "5672".into())
-            .parse()?;
-        let user = std::env::var("RABBITMQ_USER").unwrap_or_else(|_| "user".into());
-        let pass = std::env::var("RABBITMQ_PASS").unwrap_or_else(|_| "pass".into());
-
-        let addr = format!("amqp://{}:{}@{}:{}/%2f", user, pass, host, port);
+    pub async fn init(amqp: &AmqpConfig) -> Result<Self> {
         // Establish connection
-        let conn = Connection::connect(&addr, ConnectionProperties::default()).await?;
+        let conn = Connection::connect(&amqp.url(), ConnectionProperties::default()).await?;
         // Open a channel
         let channel = conn.create_channel().await?;
 
         // Enable publisher confirms
         channel.confirm_select(Default::default()).await?;
 
-        // Declare a durable queue named "order.placed"
+        // Declare a durable queue named "order.placed"; orders the machine rejects are
+        // dead-lettered to "order.failed", the arguments must match the Machine Service
         let queue = "order.placed";
+        let mut args = FieldTable::default();
+        args.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString("".into()),
+        );
+        args.insert(
+            "x-dead-letter-routing-key".into(),
+            AMQPValue::LongString("order.failed".into()),
+        );
         channel
             .queue_declare(
                 queue,
@@ -50,17 +77,37 @@ impl Producer {
                     durable: true,
                     ..Default::default()
                 },
-                FieldTable::default(),
+                args,
             )
             .await?;
 
         Ok(Producer {
+            connection: conn,
             channel,
             queue_name: queue.to_string(),
         })
     }
 
-    /// Publish an OrderMessage to the RabbitMQ queue, awaiting confirmation
+    /// Is the producer still connected to RabbitMQ?
+    pub fn is_connected(&self) -> bool {
+        self.connection.status().connected() && self.channel.status().connected()
+    }
+
+    /// Wait for outstanding publisher confirms, then close the channel and connection
+    pub async fn close(&self) -> Result<()> {
+        self.channel.wait_for_confirms().await?;
+        self.channel.close(200, "Shutting down").await?;
+        self.connection.close(200, "Shutting down").await?;
+        Ok(())
+    }
+
+    /// Publish an OrderMessage to the RabbitMQ queue, awaiting confirmation.
+    /// The trace context travels along in the message headers.
+    #[tracing::instrument(
+        name = "publish order",
+        skip_all,
+        fields(order_id = %order.order_id, otel.kind = "producer")
+    )]
     pub async fn publish(&self, order: OrderMessage) -> Result<()> {
         let payload = serde_json::to_vec(&order)?;
         // Publish to default exchange with routing key = queue name
@@ -71,7 +118,7 @@ impl Producer {
                 &self.queue_name,
                 BasicPublishOptions::default(),
                 &payload,
-                BasicProperties::default(),
+                BasicProperties::default().with_headers(telemetry::amqp_headers()),
             )
             .await?;
         // Wait for confirmation
@@ -80,6 +127,160 @@ impl Producer {
     }
 }
 
+/// Consume progress updates from the 'order.progress' queue, record them with the orders and hand
+/// them to stream subscribers until `shutdown` is cancelled
+pub async fn consume_progress(
+    amqp: &AmqpConfig,
+    orders: SharedOrders,
+    progress: ProgressSender,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let conn = Connection::connect(&amqp.url(), ConnectionProperties::default()).await?;
+    let channel = conn.create_channel().await?;
+
+    // Declare the 'order.progress' queue idempotently
+    let queue = "order.progress";
+    channel
+        .queue_declare(
+            queue,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    // Progress is only interesting while it happens, so deliveries are acknowledged automatically
+    let mut consumer = channel
+        .basic_consume(
+            queue,
+            "order-service",
+            BasicConsumeOptions {
+                no_ack: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    loop {
+        let delivery = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            delivery = consumer.next() => match delivery {
+                Some(delivery) => delivery?,
+                None => break,
+            },
+        };
+        match serde_json::from_slice::<OrderProgress>(&delivery.data) {
+            // Sending only fails when nobody is subscribed
+            Ok(update) => {
+                orders.lock().unwrap().apply(&update);
+                let _ = progress.send(update);
+            }
+            Err(e) => tracing::error!(error=%e, "Invalid progress message received, discarding"),
+        }
+    }
+
+    conn.close(200, "Shutting down").await?;
+    Ok(())
+}
+
+/// Consume orders the machine dead-lettered to 'order.failed' and refund their payments until
+/// `shutdown` is cancelled; an order whose refund fails is requeued and retried
+pub async fn consume_failed(
+    amqp: &AmqpConfig,
+    orders: SharedOrders,
+    payments: Arc<Payments>,
+    metrics: Arc<Metrics>,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let conn = Connection::connect(&amqp.url(), ConnectionProperties::default()).await?;
+    let channel = conn.create_channel().await?;
+
+    // Declare the 'order.failed' queue idempotently, the Machine Service declares it the same way
+    let queue = "order.failed";
+    channel
+        .queue_declare(
+            queue,
+            QueueDeclareOptions {
+                durable: true,
+                ..Default::default()
+            },
+            FieldTable::default(),
+        )
+        .await?;
+
+    // Failed orders are only acknowledged once they are refunded
+    let mut consumer = channel
+        .basic_consume(
+            queue,
+            "order-service-refunds",
+            BasicConsumeOptions::default(),
+            FieldTable::default(),
+        )
+        .await?;
+
+    loop {
+        let delivery = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            delivery = consumer.next() => match delivery {
+                Some(delivery) => delivery?,
+                None => break,
+            },
+        };
+        let failed = match serde_json::from_slice::<FailedOrder>(&delivery.data) {
+            Ok(failed) => failed,
+            Err(e) => {
+                tracing::error!(error=%e, "Invalid failed order received, discarding");
+                channel
+                    .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                    .await?;
+                continue;
+            }
+        };
+        let Some(payment) = failed.payment else {
+            tracing::warn!(order_id = %failed.order_id, "Failed order has no payment to refund");
+            channel
+                .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                .await?;
+            continue;
+        };
+
+        match payments.refund(&payment).await {
+            Ok(()) => {
+                tracing::info!(order_id = %failed.order_id, payment_id = %payment.id, "Refunded failed order");
+                metrics.refunds.with_label_values(&["refunded"]).inc();
+                orders.lock().unwrap().refunded(&failed.order_id);
+                channel
+                    .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                    .await?;
+            }
+            Err(e) => {
+                tracing::error!(order_id = %failed.order_id, payment_id = %payment.id, error=%e, "Refund failed, retrying");
+                metrics.refunds.with_label_values(&["failed"]).inc();
+                // Give the provider a moment before the order is delivered again
+                tokio::select! {
+                    _ = shutdown.cancelled() => {}
+                    _ = tokio::time::sleep(REFUND_RETRY_DELAY) => {}
+                }
+                channel
+                    .basic_nack(
+                        delivery.delivery_tag,
+                        BasicNackOptions {
+                            requeue: true,
+                            ..Default::default()
+                        },
+                    )
+                    .await?;
+            }
+        }
+    }
+
+    conn.close(200, "Shutting down").await?;
+    Ok(())
+}
+
 /// QueueLength represents the JSON response for queue length API
 #[derive(serde::Serialize, ToSchema)]
 pub struct QueueLength {
@@ -87,21 +288,21 @@ pub struct QueueLength {
 }
 
 /// Fetch the current number of pending messages in the 'order.placed' queue via the RabbitMQ Management API
-pub async fn fetch_queue_length() -> Result<u32> {
-    let protocol = std::env::var("RABBITMQ_MGMT_PROTOCOL").unwrap_or_else(|_| "http".into());
-    let host = std::env::var("RABBITMQ_MGMT_HOST").unwrap_or_else(|_| "localhost".into());
-    let port: u16 = std::env::var("RABBITMQ_MGMT_PORT")
-        .unwrap_or_else(|_| "15672".into())
-        .parse()?;
-    let user = std::env::var("RABBITMQ_USER").unwrap_or_else(|_| "user".into());
-    let pass = std::env::var("RABBITMQ_PASS").unwrap_or_else(|_| "pass".into());
-
-    let mgmt_url = format!("{}://{}:{}", protocol, host, port);
-    let url = format!("{}/api/queues/%2F/order.placed", mgmt_url);
+pub async fn fetch_queue_length(config: &Config) -> Result<u32> {
+    // The management API uses the credentials and vhost of the AMQP connection
+    let uri = config.amqp.uri()?;
+    let mgmt = &config.management;
+    let mgmt_url = format!("{}://{}:{}", mgmt.protocol, mgmt.host, mgmt.port);
+    let url = format!(
+        "{}/api/queues/{}/order.placed",
+        mgmt_url,
+        config::encode(&uri.vhost)
+    );
 
+    let userinfo = uri.authority.userinfo;
     let resp = reqwest::Client::new()
         .get(&url)
-        .basic_auth(user, Some(pass))
+        .basic_auth(userinfo.username, Some(userinfo.password))
         .send()
         .await?
         .json::<serde_json::Value>()